@@ -1,9 +1,27 @@
-use std::{io, mem, result, str, sync::LazyLock};
-
-use compio::{io::{util::Splittable, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, BufResult};
+use std::{
+    io, mem,
+    net::SocketAddr,
+    pin::pin,
+    result, str,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use compio::{
+    BufResult,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, util::Splittable},
+    time,
+};
+use futures_util::future::{self, Either};
+use http::{Response, Uri};
 use rand::{Rng, SeedableRng, rngs::SmallRng};
+#[cfg(feature = "rustls")]
+use rustls::{ClientConfig, KeyLog};
 
-use crate::{CloseCode, Frame, Opcode};
+use crate::{
+    CloseCode, ConnectError, Frame, Opcode, Proxy, Resolver, RttStats, SocketConfig,
+    connect::is_transient_io_error, heartbeat::Heartbeat, rtt::PingClock,
+};
 
 pub static PROTOCOL_ERROR: LazyLock<Vec<u8>> = LazyLock::new(|| {
     u16::from(CloseCode::ProtocolError)
@@ -12,9 +30,70 @@ pub static PROTOCOL_ERROR: LazyLock<Vec<u8>> = LazyLock::new(|| {
         .collect()
 });
 
+#[derive(Clone)]
 pub struct Config {
     pub read_buffer_capacity: usize,
     pub write_buffer_capacity: usize,
+    /// Maximum number of HTTP redirects to follow during the WebSocket
+    /// handshake. Redirects are not followed by default.
+    pub max_redirects: usize,
+    /// Proxy to tunnel connections through. Connections are made directly by
+    /// default.
+    pub proxy: Option<Proxy>,
+    /// Picks the proxy from the `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and
+    /// `NO_PROXY` environment variables when [`Config::proxy`] is not set. See
+    /// [`Proxy::from_env`]. Connecting fails if the proxy URI is invalid.
+    pub proxy_from_env: bool,
+    /// TLS configuration for `wss://` connections with rustls. Defaults to
+    /// [`default_tls_config`](crate::default_tls_config) if not set. Reusing the
+    /// same configuration across connections also reuses its TLS session cache.
+    #[cfg(feature = "rustls")]
+    pub tls_config: Option<Arc<ClientConfig>>,
+    /// Where to log TLS session secrets to, overriding the key log of
+    /// [`Config::tls_config`]. Meant for decrypting packet captures while
+    /// debugging, see [`tls_key_log_file`](crate::tls_key_log_file) and
+    /// [`KeyLogCallback`](crate::KeyLogCallback).
+    #[cfg(feature = "rustls")]
+    pub tls_key_log: Option<Arc<dyn KeyLog>>,
+    /// Connector for `wss://` connections with native-tls. If set, it takes
+    /// precedence over rustls when both backends are enabled.
+    #[cfg(feature = "native-tls")]
+    pub native_tls_connector: Option<native_tls::TlsConnector>,
+    /// Name to present in the TLS SNI extension, verify the server certificate
+    /// against and send in the `Host` header instead of the host of the URI.
+    pub server_name: Option<String>,
+    /// Address to connect to instead of resolving the host of the URI, e.g. a
+    /// specific colocated endpoint or a local stand-in. The host of the URI is
+    /// still used as the server name unless [`Config::server_name`] is set.
+    pub connect_addr: Option<SocketAddr>,
+    /// Resolver for the host of the URI and of the proxy. Host names are
+    /// resolved with [`SystemResolver`](crate::SystemResolver) if not set.
+    pub resolver: Option<Arc<dyn Resolver>>,
+    /// Time limit for opening the TCP connection, covering name resolution,
+    /// all connection attempts and the proxy handshake.
+    pub connect_timeout: Option<Duration>,
+    /// Time limit for a connection attempt to a single resolved address.
+    pub connect_attempt_timeout: Option<Duration>,
+    /// Delay before racing a connection attempt to the next resolved address
+    /// while the previous attempt is still pending. 250ms as recommended by
+    /// RFC 8305 by default.
+    pub connect_attempt_delay: Duration,
+    /// Options for the TCP socket of the connection.
+    pub socket: SocketConfig,
+    /// Time limit for each of the TLS and WebSocket handshakes.
+    pub handshake_timeout: Option<Duration>,
+    /// Time without receiving any data after which reading a frame fails with
+    /// [`Error::ReadTimeout`].
+    pub read_timeout: Option<Duration>,
+    /// Time limit for writing a frame, after which it fails with
+    /// [`Error::WriteTimeout`].
+    pub write_timeout: Option<Duration>,
+    /// Sends a ping whenever nothing has been written for this long, also
+    /// while a read is waiting for data. Pings are not sent by default.
+    pub ping_interval: Option<Duration>,
+    /// Time to wait for any data after sending a ping before reading fails with
+    /// [`Error::PongTimeout`].
+    pub pong_timeout: Duration,
 }
 
 impl Default for Config {
@@ -22,6 +101,27 @@ impl Default for Config {
         Self {
             read_buffer_capacity: 128 * 1024,
             write_buffer_capacity: 128 * 1024,
+            max_redirects: 0,
+            proxy: None,
+            proxy_from_env: false,
+            #[cfg(feature = "rustls")]
+            tls_config: None,
+            #[cfg(feature = "rustls")]
+            tls_key_log: None,
+            #[cfg(feature = "native-tls")]
+            native_tls_connector: None,
+            server_name: None,
+            connect_addr: None,
+            resolver: None,
+            connect_timeout: None,
+            connect_attempt_timeout: None,
+            connect_attempt_delay: Duration::from_millis(250),
+            socket: SocketConfig::default(),
+            handshake_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            ping_interval: None,
+            pong_timeout: Duration::from_secs(10),
         }
     }
 }
@@ -37,56 +137,196 @@ pub enum Error {
         code: Option<CloseCode>,
         reason: Option<String>,
     },
+    /// No data was received within [`Config::read_timeout`]. The frame being
+    /// read is lost, so all later reads and writes fail with
+    /// [`io::ErrorKind::NotConnected`].
+    #[error("Read timed out")]
+    ReadTimeout,
+    /// A frame was not written within [`Config::write_timeout`]. The frame may
+    /// have been partially written, so all later reads and writes fail with
+    /// [`io::ErrorKind::NotConnected`].
+    #[error("Write timed out")]
+    WriteTimeout,
+    /// Nothing was received within [`Config::pong_timeout`] of sending a
+    /// keepalive ping, so the connection is likely dead. All later reads and
+    /// writes fail with [`io::ErrorKind::NotConnected`].
+    #[error("Pong timed out")]
+    PongTimeout,
+    /// Reconnecting failed, see [`ReconnectingClient`](crate::ReconnectingClient).
+    #[error("Connect: {0}")]
+    Connect(#[from] ConnectError),
+}
+
+impl Error {
+    /// Whether reconnecting may succeed: network errors, timeouts, closes the
+    /// server marked as temporary with its close code and transient connect
+    /// errors, see [`ConnectError::is_transient`].
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Io(err) => is_transient_io_error(err),
+            Self::ReadTimeout | Self::WriteTimeout | Self::PongTimeout => true,
+            Self::ProtocolViolation(_) => false,
+            // A connection closed without a close code was most likely lost.
+            Self::Closed { code, .. } => code.is_none_or(CloseCode::is_transient),
+            Self::Connect(err) => err.is_transient(),
+        }
+    }
+
+    /// Whether the server sent something that violates the protocol, or closed
+    /// the connection because we did.
+    #[must_use]
+    pub fn is_protocol_fault(&self) -> bool {
+        match self {
+            Self::ProtocolViolation(_) => true,
+            Self::Closed { code, .. } => code.is_some_and(CloseCode::is_protocol_fault),
+            Self::Connect(err) => err.is_protocol_fault(),
+            _ => false,
+        }
+    }
+
+    /// Returns the close code the server closed the connection with.
+    #[must_use]
+    pub fn close_code(&self) -> Option<CloseCode> {
+        match self {
+            Self::Closed { code, .. } => *code,
+            _ => None,
+        }
+    }
 }
 
-pub type BufResult<T> = (result::Result<T, Error>, Vec<u8>);
 pub type Result<T> = result::Result<T, Error>;
 
+/// Position of a frame in the read buffer of a [`Client`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BufferedFrame {
+    pub(crate) fin: bool,
+    pub(crate) opcode: Opcode,
+    start: usize,
+    len: usize,
+}
+
+/// A WebSocket client over a stream that is split into a read and a write
+/// half, so that keepalive pings can be written while a read is pending.
 pub struct Client<S>
-// where
-//     S: AsyncWrite,
+where
+    S: Splittable,
 {
-    stream: S,
+    read_half: S::ReadHalf,
     read_buffer: Vec<u8>,
     read_consumed: usize,
-    write_buffer: Vec<u8>,
-    write_rng: SmallRng,
-    // read_half: ReadHalf<S>,
-    // write_half: WriteHalf<S>,
+    write_half: WriteHalf<S::WriteHalf>,
+    read_timeout: Option<Duration>,
+    heartbeat: Option<Heartbeat>,
+    ping_clock: PingClock,
+    rtt: RttStats,
+    /// Set once a timeout cancelled a read or write midway, after which the
+    /// buffers and the stream are out of step.
+    failed: bool,
+    uri: Option<Uri>,
+    response: Option<Response<()>>,
 }
 
-impl<S> Client<S> {
+/// The write half of a [`Client`]'s stream with what it takes to write frames.
+struct WriteHalf<W> {
+    inner: W,
+    buffer: Vec<u8>,
+    rng: SmallRng,
+    timeout: Option<Duration>,
+}
+
+impl<S> Client<S>
+where
+    S: Splittable,
+{
     pub fn new(stream: S, config: &Config) -> Self {
+        let (read_half, write_half) = Splittable::split(stream);
         Self {
-            stream,
+            read_half,
             read_buffer: Vec::with_capacity(config.read_buffer_capacity),
             read_consumed: 0,
-            write_buffer: Vec::with_capacity(config.write_buffer_capacity),
-            write_rng: SmallRng::from_os_rng(),
-            // read_half: ReadHalf {
-            //     inner: read_half,
-            //     buffer: Vec::with_capacity(config.read_buffer_capacity),
-            //     consumed: 0,
-            // },
-            // write_half: WriteHalf {
-            //     inner: write_half,
-            //     rng: SmallRng::from_os_rng(),
-            //     buffer: Vec::with_capacity(config.write_buffer_capacity),
-            // },
+            write_half: WriteHalf {
+                inner: write_half,
+                buffer: Vec::with_capacity(config.write_buffer_capacity),
+                rng: SmallRng::from_os_rng(),
+                timeout: config.write_timeout,
+            },
+            read_timeout: config.read_timeout,
+            heartbeat: Heartbeat::new(config),
+            ping_clock: PingClock::new(),
+            rtt: RttStats::default(),
+            failed: false,
+            uri: None,
+            response: None,
         }
     }
+
+    pub(crate) fn with_handshake(mut self, uri: Uri, response: Response<()>) -> Self {
+        self.uri = Some(uri);
+        self.response = Some(response);
+        self
+    }
+
+    /// Returns the URI the WebSocket handshake was completed against. This
+    /// differs from the URI passed to the connect function if the server
+    /// redirected the client.
+    #[must_use]
+    pub fn uri(&self) -> Option<&Uri> {
+        self.uri.as_ref()
+    }
+
+    /// Returns the `101 Switching Protocols` response received during the
+    /// WebSocket handshake, or `None` if the client was created over a stream
+    /// that was not upgraded by this crate.
+    #[must_use]
+    pub fn response(&self) -> Option<&Response<()>> {
+        self.response.as_ref()
+    }
+
+    /// Returns the round-trip times measured with the pings sent by
+    /// [`Client::send_rtt_ping`] and keepalive pings. Pongs are matched while
+    /// reading frames.
+    #[must_use]
+    pub fn rtt(&self) -> &RttStats {
+        &self.rtt
+    }
 }
 
 impl<S> Client<S>
 where
-    S: AsyncRead,
+    S: Splittable,
+    S::ReadHalf: AsyncRead,
+    S::WriteHalf: AsyncWrite,
 {
     const CHUNK_SIZE: usize = 4096;
 
+    /// Reads the next frame. Keepalive pings are sent while waiting for data
+    /// if [`Config::ping_interval`] is set.
+    pub async fn read_frame(&mut self) -> Result<Frame<'_>> {
+        let frame = self.read_frame_inner().await?;
+        Ok(self.buffered_frame(frame))
+    }
+
+    /// Returns a frame previously read by [`Client::read_frame_inner`]. Only
+    /// valid until the next frame is read.
+    pub(crate) fn buffered_frame(&self, frame: BufferedFrame) -> Frame<'_> {
+        Frame {
+            fin: frame.fin,
+            opcode: frame.opcode,
+            data: &self.read_buffer[frame.start..frame.start + frame.len],
+        }
+    }
+
+    /// Reads the next frame into the read buffer. Unlike [`Client::read_frame`]
+    /// the result does not borrow the client, which lets callers decide what
+    /// to do with the client before looking at the frame.
     #[inline]
-    async fn read_frame_inner(&mut self) -> Result<Frame> {
+    pub(crate) async fn read_frame_inner(&mut self) -> Result<BufferedFrame> {
         const HEADER_LEN: usize = 2;
 
+        if self.failed {
+            return Err(failed_error());
+        }
         if self.read_consumed > 0
             && self.read_buffer.len() > self.read_buffer.capacity() - Self::CHUNK_SIZE
         {
@@ -190,20 +430,106 @@ where
 
         self.ensure_read(length).await?;
 
-        let data = &self.read_buffer[self.read_consumed..self.read_consumed + length];
+        let start = self.read_consumed;
+        let data = &self.read_buffer[start..start + length];
         self.read_consumed += length;
 
-        Ok(Frame { fin, opcode, data })
+        let rtt = match opcode {
+            Opcode::Pong => self.ping_clock.rtt(data, Instant::now()),
+            _ => None,
+        };
+        if let Some(rtt) = rtt {
+            self.rtt.record(rtt);
+        }
+
+        Ok(BufferedFrame {
+            fin,
+            opcode,
+            start,
+            len: length,
+        })
     }
 
     #[inline]
     async fn ensure_read(&mut self, len: usize) -> Result<()> {
         while self.read_buffer.len() < self.read_consumed + len {
+            let read_deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
             let buffer = mem::take(&mut self.read_buffer);
-            self.stream.read_exact()
-            let (res, buffer) = self.stream.read_extend(buffer, Self::CHUNK_SIZE).await;
+            // The read is never cancelled to send a ping, as it would lose
+            // whatever it already received. Pings are written on the write half
+            // while it is pending instead.
+            let mut read = pin!(self.read_half.read_extend(buffer, Self::CHUNK_SIZE));
+            let (res, buffer) = loop {
+                let heartbeat_deadline = self.heartbeat.as_ref().map(Heartbeat::deadline);
+                let Some(deadline) = heartbeat_deadline.into_iter().chain(read_deadline).min()
+                else {
+                    break read.await;
+                };
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                if let Ok(res) = time::timeout(timeout, read.as_mut()).await {
+                    break res;
+                }
+
+                let now = Instant::now();
+                let pong_overdue = self
+                    .heartbeat
+                    .as_ref()
+                    .and_then(Heartbeat::pong_deadline)
+                    .is_some_and(|deadline| deadline <= now);
+                if pong_overdue || read_deadline.is_some_and(|deadline| deadline <= now) {
+                    // A read is only cancelled when the connection is failed
+                    // anyway.
+                    self.failed = true;
+                    return Err(if pong_overdue {
+                        Error::PongTimeout
+                    } else {
+                        Error::ReadTimeout
+                    });
+                }
+                if !self
+                    .heartbeat
+                    .as_ref()
+                    .is_some_and(|heartbeat| heartbeat.ping_due(now))
+                {
+                    continue;
+                }
+
+                let payload = self.ping_clock.ping_payload(now);
+                let dst = self.write_half.encode_control(Frame {
+                    fin: true,
+                    opcode: Opcode::Ping,
+                    data: &payload,
+                });
+                let mut write = pin!(self.write_half.write_all(dst));
+                // Keep reading while the ping is written, in case the halves
+                // share a lock and the write has to wait for the read.
+                let (res, write_res) = match future::select(read.as_mut(), write.as_mut()).await {
+                    Either::Left((res, _)) => (Some(res), None),
+                    Either::Right((write_res, _)) => (None, Some(write_res)),
+                };
+                let write_res = match write_res {
+                    Some(write_res) => write_res,
+                    None => write.await,
+                };
+                if let Err(err) = write_res {
+                    self.failed = true;
+                    return Err(err);
+                }
+                if let Some(heartbeat) = &mut self.heartbeat {
+                    heartbeat.on_ping(now);
+                    heartbeat.on_write(Instant::now());
+                }
+                if let Some(res) = res {
+                    break res;
+                }
+            };
             self.read_buffer = buffer;
-            let _ = res?;
+            if res? == 0 {
+                return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            if let Some(heartbeat) = &mut self.heartbeat {
+                heartbeat.on_read();
+            }
         }
         Ok(())
     }
@@ -211,9 +537,10 @@ where
 
 impl<S> Client<S>
 where
-    S: AsyncWrite,
+    S: Splittable,
+    S::WriteHalf: AsyncWrite,
 {
-    pub async fn send_ping(&mut self, data: &[u8]) -> io::Result<()> {
+    pub async fn send_ping(&mut self, data: &[u8]) -> Result<()> {
         self.send(Frame {
             fin: true,
             opcode: Opcode::Ping,
@@ -222,7 +549,14 @@ where
         .await
     }
 
-    pub async fn send_pong(&mut self, data: &[u8]) -> io::Result<()> {
+    /// Sends a ping carrying a sequence number and timestamp, so the round-trip
+    /// time is recorded in [`Client::rtt`] once the pong is read.
+    pub async fn send_rtt_ping(&mut self) -> Result<()> {
+        let payload = self.ping_clock.ping_payload(Instant::now());
+        self.send_ping(&payload).await
+    }
+
+    pub async fn send_pong(&mut self, data: &[u8]) -> Result<()> {
         self.send(Frame {
             fin: true,
             opcode: Opcode::Pong,
@@ -231,7 +565,7 @@ where
         .await
     }
 
-    pub async fn send_binary(&mut self, data: &[u8]) -> io::Result<()> {
+    pub async fn send_binary(&mut self, data: &[u8]) -> Result<()> {
         self.send(Frame {
             fin: true,
             opcode: Opcode::Binary,
@@ -240,7 +574,7 @@ where
         .await
     }
 
-    pub async fn send_text(&mut self, data: &[u8]) -> io::Result<()> {
+    pub async fn send_text(&mut self, data: &[u8]) -> Result<()> {
         self.send(Frame {
             fin: true,
             opcode: Opcode::Text,
@@ -249,7 +583,7 @@ where
         .await
     }
 
-    pub async fn send_close(&mut self, data: &[u8]) -> io::Result<()> {
+    pub async fn send_close(&mut self, data: &[u8]) -> Result<()> {
         self.send(Frame {
             fin: true,
             opcode: Opcode::Close,
@@ -259,23 +593,160 @@ where
     }
 
     #[inline]
-    async fn send(&mut self, frame: Frame<'_>) -> io::Result<()> {
+    async fn send(&mut self, frame: Frame<'_>) -> Result<()> {
         self.write_frame(frame).await
     }
 
-    pub async fn write_frame(&mut self, frame: Frame<'_>) -> io::Result<()> {
-        let mut dst = mem::take(&mut self.write_buffer);
-        frame.encode(&mut dst, self.write_rng.random::<u32>().to_ne_bytes());
-        let BufResult(res, buffer) = self.stream.write_all(dst).await;
-        self.write_buffer = buffer;
-        res.map(|_| ())
+    pub async fn write_frame(&mut self, frame: Frame<'_>) -> Result<()> {
+        let dst = self.write_half.encode(frame);
+        self.write_all(dst).await
+    }
+
+    pub async fn write_control_frame(&mut self, frame: Frame<'_>) -> Result<()> {
+        let dst = self.write_half.encode_control(frame);
+        self.write_all(dst).await
+    }
+
+    #[inline]
+    async fn write_all(&mut self, dst: Vec<u8>) -> Result<()> {
+        if self.failed {
+            return Err(failed_error());
+        }
+        let res = self.write_half.write_all(dst).await;
+        if matches!(res, Err(Error::WriteTimeout)) {
+            self.failed = true;
+        }
+        res?;
+        if let Some(heartbeat) = &mut self.heartbeat {
+            heartbeat.on_write(Instant::now());
+        }
+        Ok(())
+    }
+}
+
+impl<W> WriteHalf<W>
+where
+    W: AsyncWrite,
+{
+    fn encode(&mut self, frame: Frame<'_>) -> Vec<u8> {
+        let mut dst = mem::take(&mut self.buffer);
+        frame.encode(&mut dst, self.rng.random::<u32>().to_ne_bytes());
+        dst
     }
 
-    pub async fn write_control_frame(&mut self, frame: Frame<'_>) -> io::Result<()> {
-        let mut dst = mem::take(&mut self.write_buffer);
-        frame.encode_control(&mut dst, self.write_rng.random::<u32>().to_ne_bytes());
-        let BufResult(res, buffer) = self.stream.write_all(dst).await;
-        self.write_buffer = buffer;
-        res.map(|_| ())
+    fn encode_control(&mut self, frame: Frame<'_>) -> Vec<u8> {
+        let mut dst = mem::take(&mut self.buffer);
+        frame.encode_control(&mut dst, self.rng.random::<u32>().to_ne_bytes());
+        dst
+    }
+
+    #[inline]
+    async fn write_all(&mut self, dst: Vec<u8>) -> Result<()> {
+        let write = self.inner.write_all(dst);
+        let BufResult(res, buffer) = match self.timeout {
+            Some(timeout) => time::timeout(timeout, write)
+                .await
+                .map_err(|_| Error::WriteTimeout)?,
+            None => write.await,
+        };
+        self.buffer = buffer;
+        res?;
+        Ok(())
+    }
+}
+
+/// Returned by reads and writes once a timeout failed the connection.
+fn failed_error() -> Error {
+    io::Error::new(
+        io::ErrorKind::NotConnected,
+        "the connection failed after a timeout",
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use compio::net::{TcpListener, TcpStream};
+    use test_case::test_case;
+
+    use super::*;
+
+    fn closed(code: u16) -> Error {
+        Error::Closed {
+            code: CloseCode::try_from(code).ok(),
+            reason: None,
+        }
+    }
+
+    #[test_case(closed(1000) => false; "normal close")]
+    #[test_case(closed(1001) => true; "going away")]
+    #[test_case(closed(1012) => true; "service restart")]
+    #[test_case(closed(1002) => false; "protocol error close")]
+    #[test_case(closed(1008) => false; "policy violation")]
+    #[test_case(closed(4000) => false; "private close code")]
+    #[test_case(Error::Closed { code: None, reason: None } => true; "no close code")]
+    #[test_case(Error::ReadTimeout => true; "read timeout")]
+    #[test_case(Error::WriteTimeout => true; "write timeout")]
+    #[test_case(Error::PongTimeout => true; "pong timeout")]
+    #[test_case(Error::ProtocolViolation("masked frame") => false; "protocol violation")]
+    #[test_case(Error::Io(io::ErrorKind::ConnectionReset.into()) => true; "connection reset")]
+    #[test_case(Error::Connect(ConnectError::HandshakeTimeout) => true; "handshake timeout")]
+    #[test_case(Error::Connect(ConnectError::InvalidUriScheme) => false; "invalid scheme")]
+    fn test_is_transient(err: Error) -> bool {
+        err.is_transient()
+    }
+
+    #[test_case(closed(1002) => true; "protocol error close")]
+    #[test_case(closed(1009) => true; "message too big")]
+    #[test_case(closed(1011) => false; "internal error")]
+    #[test_case(Error::Closed { code: None, reason: None } => false; "no close code")]
+    #[test_case(Error::ProtocolViolation("masked frame") => true; "protocol violation")]
+    #[test_case(Error::ReadTimeout => false; "read timeout")]
+    #[test_case(Error::PongTimeout => false; "pong timeout")]
+    #[test_case(Error::Connect(ConnectError::InvalidWebSocketAcceptHeader) => true; "invalid accept header")]
+    fn test_is_protocol_fault(err: Error) -> bool {
+        err.is_protocol_fault()
+    }
+
+    #[test_case(closed(1000) => Some(CloseCode::Normal); "normal close")]
+    #[test_case(closed(1012) => Some(CloseCode::ServiceRestart); "service restart")]
+    #[test_case(Error::Closed { code: None, reason: None } => None; "no close code")]
+    #[test_case(Error::ReadTimeout => None; "read timeout")]
+    #[test_case(Error::WriteTimeout => None; "write timeout")]
+    #[test_case(Error::PongTimeout => None; "pong timeout")]
+    fn test_close_code(err: Error) -> Option<CloseCode> {
+        err.close_code()
+    }
+
+    #[compio::test]
+    async fn test_read_after_read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server_stream, _) = listener.accept().await.unwrap();
+        let config = Config {
+            read_timeout: Some(Duration::from_millis(10)),
+            ..Config::default()
+        };
+        let mut client = Client::new(stream, &config);
+
+        // Only the header of a frame arrives before the timeout.
+        let BufResult(result, _) = server_stream.write_all(vec![0x82, 0x02]).await;
+        result.unwrap();
+        assert!(matches!(client.read_frame().await, Err(Error::ReadTimeout)));
+
+        let BufResult(result, _) = server_stream.write_all(vec![0xAA, 0xBB]).await;
+        result.unwrap();
+        for _ in 0..2 {
+            let Err(Error::Io(err)) = client.read_frame().await else {
+                panic!("read after a timeout must fail");
+            };
+            assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        }
+        let Err(Error::Io(err)) = client.send_text(b"hi").await else {
+            panic!("write after a timeout must fail");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
     }
 }
@@ -110,6 +110,41 @@ impl CloseCode {
     }
 }
 
+impl CloseCode {
+    /// Whether the server closed the connection for a reason that may go away
+    /// by reconnecting, such as a restart, overload or an internal error.
+    /// [`CloseCode::Normal`] means the server is done with the connection and
+    /// is not transient. Codes defined by libraries and applications are not
+    /// classified and count as not transient.
+    #[must_use]
+    pub fn is_transient(self) -> bool {
+        matches!(
+            self,
+            Self::GoingAway
+                | Self::NoStatusReceived
+                | Self::Abnormal
+                | Self::InternalError
+                | Self::ServiceRestart
+                | Self::TryAgainLater
+                | Self::BadGateway
+        )
+    }
+
+    /// Whether the connection was closed because one side violated the
+    /// protocol or sent data the other side could not process.
+    #[must_use]
+    pub fn is_protocol_fault(self) -> bool {
+        matches!(
+            self,
+            Self::ProtocolError
+                | Self::UnsupportedData
+                | Self::InvalidFramePayloadData
+                | Self::MessageTooBig
+                | Self::MandatoryExtension
+        )
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum CloseCodeParseError {
     #[error("Invalid WebSocket close code: {0}")]
@@ -1,85 +1,454 @@
-use std::{io, result, sync::Arc};
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+use std::{borrow::Cow, io, path::Path, result};
 
 use base64::{Engine, prelude::BASE64_STANDARD};
 use compio::BufResult;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
 use compio::tls::{TlsConnector, TlsStream};
 use compio::{
-    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
-    net::TcpStream,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, util::Splittable},
+    net::{TcpStream, UnixStream},
+    time,
+};
+use http::{
+    Response, StatusCode, Uri, Version,
+    header::{CONTENT_LENGTH, LOCATION, SEC_WEBSOCKET_ACCEPT},
+    uri::PathAndQuery,
 };
-use http::Uri;
 use rand::Rng;
-use rustls::ClientConfig;
+#[cfg(feature = "rustls")]
+use rustls::{ClientConfig, client::VerifierBuilderError, pki_types::pem};
 use sha1::{Digest, Sha1};
 
-use crate::{Client, Config};
+#[cfg(feature = "webpki-roots")]
+use crate::default_tls_config;
+#[cfg(feature = "rustls")]
+use crate::tls::{is_certificate_error, is_pin_mismatch};
+use crate::{Client, Config, MaybeTlsStream, Proxy, happy_eyeballs, proxy::socks5_reply_message};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConnectError {
     #[error("IO: {0}")]
     Io(#[from] io::Error),
-    #[error("Invalid handshake response: {0}")]
-    InvalidHandshakeResponse(String),
+    #[error("Invalid handshake response: {}", .0.status())]
+    InvalidHandshakeResponse(Box<Response<Vec<u8>>>),
+    #[error("Invalid proxy response: {}", .0.status())]
+    InvalidProxyResponse(Box<Response<Vec<u8>>>),
+    #[error("SOCKS5 proxy: {0}")]
+    Socks5(&'static str),
+    /// The proxy URI taken from the environment is invalid or uses a scheme
+    /// that is not supported, see [`Proxy::from_env`].
+    #[error("Invalid proxy URI: {0}")]
+    InvalidProxy(&'static str),
+    /// The SOCKS5 proxy could not open the tunnel, with the reply code it
+    /// answered with.
+    #[error("SOCKS5 proxy: {}", socks5_reply_message(*.0))]
+    Socks5Reply(u8),
+    #[cfg(feature = "rustls")]
+    #[error("Invalid PEM: {0}")]
+    InvalidPem(pem::Error),
+    #[cfg(feature = "rustls")]
+    #[error("Invalid client private key: {0}")]
+    InvalidClientKey(rustls::Error),
+    #[cfg(feature = "rustls")]
+    #[error("Client private key does not match the certificate")]
+    ClientKeyMismatch,
+    #[cfg(feature = "rustls")]
+    #[error("Invalid root certificate store: {0}")]
+    InvalidRootStore(VerifierBuilderError),
+    #[cfg(all(feature = "rustls", not(feature = "webpki-roots")))]
+    #[error("No TLS configuration set and no default root certificates available")]
+    MissingTlsConfig,
+    #[cfg(feature = "rustls")]
+    #[error("Server certificate chain does not match any pinned public key")]
+    CertificatePinMismatch,
+    #[error("Malformed handshake response: {0}")]
+    MalformedHandshakeResponse(&'static str),
     #[error("Invalid Sec-WebSocket-Accept header")]
     InvalidWebSocketAcceptHeader,
     #[error("Attempted to connect with invalid URI scheme")]
     InvalidUriScheme,
+    #[error("Attempted to connect to a URI without a host")]
+    MissingHost,
+    #[error("Handshake timed out")]
+    HandshakeTimeout,
+    #[error("Redirect without a valid Location header")]
+    InvalidRedirectLocation,
+    #[error("Refused to follow a redirect from a wss:// URI to an unencrypted one")]
+    InsecureRedirect,
+    #[error("Redirected to {0}, which needs Client::connect to change the URI scheme")]
+    SchemeChangingRedirect(Uri),
+    #[error("Exceeded the limit of {0} redirects")]
+    TooManyRedirects(usize),
 }
 
 pub type ConnectResult<T> = result::Result<T, ConnectError>;
 
-impl Client<TlsStream<TcpStream>> {
-    pub async fn connect_tls(uri: &Uri, config: &Config) -> ConnectResult<Self> {
-        if uri.scheme_str() != Some("wss") {
-            return Err(ConnectError::InvalidUriScheme);
+impl ConnectError {
+    /// Whether connecting again later may succeed: network errors, timeouts,
+    /// handshake responses such as `429 Too Many Requests` or
+    /// `503 Service Unavailable` and proxies failing to reach the server.
+    /// Rejected credentials, missing endpoints, certificate failures and
+    /// misconfiguration are not transient.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Io(err) => is_transient_io_error(err),
+            Self::InvalidHandshakeResponse(response) | Self::InvalidProxyResponse(response) => {
+                is_transient_status(response.status())
+            }
+            // General failure, network or host unreachable, connection refused
+            // and TTL expired.
+            Self::Socks5Reply(reply) => matches!(reply, 0x01 | 0x03..=0x06),
+            Self::HandshakeTimeout => true,
+            _ => false,
         }
+    }
 
-        let mut root_store = rustls::RootCertStore::empty();
-        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    /// Whether the server answered the handshake with something that is not a
+    /// valid WebSocket upgrade.
+    #[must_use]
+    pub fn is_protocol_fault(&self) -> bool {
+        matches!(
+            self,
+            Self::MalformedHandshakeResponse(_) | Self::InvalidWebSocketAcceptHeader
+        )
+    }
 
-        let tls_config = ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
+    /// Whether the server certificate was rejected, either by certificate
+    /// verification or pinning. Failures of the native-tls backend are not
+    /// detected.
+    #[must_use]
+    pub fn is_certificate_failure(&self) -> bool {
+        match self {
+            #[cfg(feature = "rustls")]
+            Self::CertificatePinMismatch => true,
+            #[cfg(feature = "rustls")]
+            Self::Io(err) => is_certificate_error(err),
+            _ => false,
+        }
+    }
+}
 
-        let connector = TlsConnector::from(Arc::new(tls_config));
+/// Whether an I/O error is caused by the network or the peer rather than by
+/// the local configuration.
+pub(crate) fn is_transient_io_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::NetworkDown
+    )
+}
 
-        // Connect, upgrade to TLS and perform WebSocket handshake.
-        let stream = TcpStream::connect(format!(
-            "{}:{}",
-            uri.host().unwrap_or_default(),
-            uri.port_u16().unwrap_or(443)
-        ))
-        .await?;
-        TcpStream::set_nodelay(&stream, true)?;
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
 
-        let stream = connector
-            .connect(uri.host().unwrap_or_default(), stream)
-            .await?;
-        Ok(Self::new(handshake(stream, uri).await?, config))
+/// Upper bound on how much of a rejected handshake's response body is kept for
+/// diagnostics.
+const MAX_RESPONSE_BODY_LEN: usize = 64 * 1024;
+
+impl Client<MaybeTlsStream> {
+    /// Connects to either a `ws://` or a `wss://` URI. Unlike
+    /// [`Client::connect_plain`] and [`Client::connect_tls`], redirects from
+    /// `ws://` to `wss://` are followed. Redirects the other way are refused
+    /// with [`ConnectError::InsecureRedirect`].
+    pub async fn connect(uri: &Uri, config: &Config) -> ConnectResult<Self> {
+        connect(uri, config, async |uri: &Uri, config: &Config| {
+            match uri.scheme_str() {
+                Some("ws") => Ok(MaybeTlsStream::Plain(open_tcp(uri, config).await?)),
+                #[cfg(any(feature = "rustls", feature = "native-tls"))]
+                Some("wss") => Ok(MaybeTlsStream::Tls(open_tls(uri, config).await?)),
+                _ => Err(ConnectError::InvalidUriScheme),
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+impl Client<TlsStream<TcpStream>> {
+    /// Connects to a `wss://` URI. Redirects to `ws://` URIs fail with
+    /// [`ConnectError::InsecureRedirect`].
+    pub async fn connect_tls(uri: &Uri, config: &Config) -> ConnectResult<Self> {
+        connect(uri, config, open_tls).await
     }
 }
 
 impl Client<TcpStream> {
+    /// Connects to a `ws://` URI. Redirects to `wss://` URIs fail with
+    /// [`ConnectError::SchemeChangingRedirect`], use [`Client::connect`] to
+    /// follow them.
     pub async fn connect_plain(uri: &Uri, config: &Config) -> ConnectResult<Self> {
-        if uri.scheme_str() != Some("ws") {
-            return Err(ConnectError::InvalidUriScheme);
+        connect(uri, config, async |redirect: &Uri, config: &Config| {
+            if redirect.scheme() != uri.scheme() {
+                return Err(ConnectError::SchemeChangingRedirect(redirect.clone()));
+            }
+            open_tcp(redirect, config).await
+        })
+        .await
+    }
+}
+
+impl Client<UnixStream> {
+    /// Connects to a WebSocket server listening on the Unix domain socket at
+    /// `path`. `uri` only supplies the request path and the `Host` header, e.g.
+    /// `ws://localhost/v1/events`, and its scheme is not checked. The `Host`
+    /// header can be overridden with [`Config::server_name`].
+    pub async fn connect_unix(
+        path: impl AsRef<Path>,
+        uri: &Uri,
+        config: &Config,
+    ) -> ConnectResult<Self> {
+        let stream = UnixStream::connect(path).await?;
+        Self::connect_with_stream(stream, uri, config).await
+    }
+}
+
+impl<S> Client<S>
+where
+    S: AsyncRead + AsyncWrite + Splittable,
+{
+    /// Performs the WebSocket handshake for `uri` over a stream the caller has
+    /// already established, such as a Unix socket, a custom TLS session or a
+    /// proxy tunnel. The scheme of `uri` is not checked and only its host and
+    /// path are used. Redirects cannot be followed over a single stream and
+    /// are returned as [`ConnectError::InvalidHandshakeResponse`].
+    ///
+    /// The stream is split after the handshake, so that keepalive pings can be
+    /// written while a read is pending.
+    pub async fn connect_with_stream(stream: S, uri: &Uri, config: &Config) -> ConnectResult<Self> {
+        let (stream, response) = handshake(stream, uri, config).await?;
+        Ok(Self::new(stream, config).with_handshake(uri.clone(), response))
+    }
+}
+
+/// Opens a stream with `open` and performs the WebSocket handshake over it,
+/// starting over at the new location whenever the server redirects us and
+/// [`Config::max_redirects`] allows it.
+async fn connect<S>(
+    uri: &Uri,
+    config: &Config,
+    mut open: impl AsyncFnMut(&Uri, &Config) -> ConnectResult<S>,
+) -> ConnectResult<Client<S>>
+where
+    S: AsyncRead + AsyncWrite + Splittable,
+{
+    let mut uri = uri.clone();
+    let mut config = Cow::Borrowed(config);
+    let mut redirects = 0;
+    loop {
+        let stream = open(&uri, config.as_ref()).await?;
+        match handshake(stream, &uri, &config).await {
+            Ok((stream, response)) => {
+                return Ok(Client::new(stream, &config).with_handshake(uri, response));
+            }
+            Err(ConnectError::InvalidHandshakeResponse(response))
+                if config.max_redirects > 0 && is_redirect(response.status()) =>
+            {
+                if redirects == config.max_redirects {
+                    return Err(ConnectError::TooManyRedirects(config.max_redirects));
+                }
+                let redirect = redirect_uri(&uri, &response)?;
+                if redirect.host() != uri.host() {
+                    // The overrides only apply to the host they were configured for.
+                    let config = config.to_mut();
+                    config.server_name = None;
+                    config.connect_addr = None;
+                }
+                uri = redirect;
+                redirects += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+async fn open_tls(uri: &Uri, config: &Config) -> ConnectResult<TlsStream<TcpStream>> {
+    if uri.scheme_str() != Some("wss") {
+        return Err(ConnectError::InvalidUriScheme);
+    }
+
+    let connector = tls_connector(config)?;
+
+    // Connect and upgrade to TLS.
+    let stream = connect_tcp(uri, config).await?;
+
+    let server_name = match &config.server_name {
+        Some(server_name) => server_name,
+        None => host(uri)?,
+    };
+    let tls_handshake = connector.connect(server_name, stream);
+    let res = match config.handshake_timeout {
+        Some(timeout) => time::timeout(timeout, tls_handshake)
+            .await
+            .map_err(|_| ConnectError::HandshakeTimeout)?,
+        None => tls_handshake.await,
+    };
+    res.map_err(|err| {
+        #[cfg(feature = "rustls")]
+        if is_pin_mismatch(&err) {
+            return ConnectError::CertificatePinMismatch;
         }
+        ConnectError::Io(err)
+    })
+}
 
-        // Connect and perform WebSocket handshake.
-        let stream = TcpStream::connect(format!(
-            "{}:{}",
-            uri.host().unwrap_or_default(),
-            uri.port_u16().unwrap_or(80)
-        ))
-        .await?;
-        TcpStream::set_nodelay(&stream, true)?;
+/// Picks the TLS backend: native-tls if [`Config::native_tls_connector`] is
+/// set or rustls is not enabled, rustls otherwise.
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+fn tls_connector(config: &Config) -> ConnectResult<TlsConnector> {
+    #[cfg(feature = "native-tls")]
+    if let Some(connector) = &config.native_tls_connector {
+        return Ok(TlsConnector::from(connector.clone()));
+    }
+    default_tls_connector(config)
+}
 
-        Ok(Self::new(handshake(stream, uri).await?, config))
+#[cfg(feature = "rustls")]
+fn default_tls_connector(config: &Config) -> ConnectResult<TlsConnector> {
+    #[cfg(feature = "webpki-roots")]
+    let mut tls_config = config.tls_config.clone().unwrap_or_else(default_tls_config);
+    #[cfg(not(feature = "webpki-roots"))]
+    let mut tls_config = config
+        .tls_config
+        .clone()
+        .ok_or(ConnectError::MissingTlsConfig)?;
+    if let Some(key_log) = &config.tls_key_log {
+        // Clones share the session cache of the original configuration.
+        let mut with_key_log = ClientConfig::clone(&tls_config);
+        with_key_log.key_log = key_log.clone();
+        tls_config = Arc::new(with_key_log);
     }
+    Ok(TlsConnector::from(tls_config))
 }
 
-/// Performs a WebSocket handshake on an existing TCP connection via HTTP 1.
-async fn handshake<T>(mut stream: T, uri: &Uri) -> ConnectResult<T>
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+fn default_tls_connector(_config: &Config) -> ConnectResult<TlsConnector> {
+    let connector = native_tls::TlsConnector::new().map_err(io::Error::other)?;
+    Ok(TlsConnector::from(connector))
+}
+
+async fn open_tcp(uri: &Uri, config: &Config) -> ConnectResult<TcpStream> {
+    if uri.scheme_str() != Some("ws") {
+        return Err(ConnectError::InvalidUriScheme);
+    }
+
+    connect_tcp(uri, config).await
+}
+
+/// Opens a TCP connection to the host of `uri`, or [`Config::connect_addr`] if
+/// set, tunnelled through a proxy if one is configured for it.
+async fn connect_tcp(uri: &Uri, config: &Config) -> ConnectResult<TcpStream> {
+    match config.connect_timeout {
+        Some(timeout) => time::timeout(timeout, connect_tcp_inner(uri, config))
+            .await
+            .unwrap_or_else(|_| Err(happy_eyeballs::timed_out().into())),
+        None => connect_tcp_inner(uri, config).await,
+    }
+}
+
+async fn connect_tcp_inner(uri: &Uri, config: &Config) -> ConnectResult<TcpStream> {
+    let (host, port) = match config.connect_addr {
+        Some(addr) => (Cow::Owned(addr.ip().to_string()), addr.port()),
+        None => (Cow::Borrowed(host(uri)?), port(uri)),
+    };
+
+    let proxy = match &config.proxy {
+        Some(proxy) => Some(Cow::Borrowed(proxy)),
+        None if config.proxy_from_env => Proxy::from_env(uri)?.map(Cow::Owned),
+        None => None,
+    };
+    Ok(match proxy {
+        Some(proxy) => proxy.connect(&host, port, config).await?,
+        None => happy_eyeballs::connect(&host, port, config).await?,
+    })
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+/// Resolves the `Location` of a redirect response against the URI that was
+/// redirected. HTTP schemes are mapped onto their WebSocket counterparts.
+/// Redirects from `wss://` to `ws://` are refused so that a connection is never
+/// downgraded to plaintext.
+fn redirect_uri(uri: &Uri, response: &Response<Vec<u8>>) -> ConnectResult<Uri> {
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|location| location.to_str().ok()?.parse::<Uri>().ok())
+        .ok_or(ConnectError::InvalidRedirectLocation)?;
+
+    let scheme = match location.scheme_str() {
+        None => uri.scheme_str().unwrap_or_default(),
+        Some("ws" | "http") => "ws",
+        Some("wss" | "https") => "wss",
+        Some(_) => return Err(ConnectError::InvalidRedirectLocation),
+    };
+    if uri.scheme_str() == Some("wss") && scheme == "ws" {
+        return Err(ConnectError::InsecureRedirect);
+    }
+    let authority = location
+        .authority()
+        .or(uri.authority())
+        .ok_or(ConnectError::InvalidRedirectLocation)?;
+    let path_and_query = location.path_and_query().map_or("/", PathAndQuery::as_str);
+
+    Uri::builder()
+        .scheme(scheme)
+        .authority(authority.as_str())
+        .path_and_query(path_and_query)
+        .build()
+        .map_err(|_| ConnectError::InvalidRedirectLocation)
+}
+
+/// Performs a WebSocket handshake on an existing TCP connection via HTTP 1,
+/// within [`Config::handshake_timeout`] if set.
+async fn handshake<T>(stream: T, uri: &Uri, config: &Config) -> ConnectResult<(T, Response<()>)>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    match config.handshake_timeout {
+        Some(timeout) => time::timeout(timeout, handshake_inner(stream, uri, config))
+            .await
+            .map_err(|_| ConnectError::HandshakeTimeout)?,
+        None => handshake_inner(stream, uri, config).await,
+    }
+}
+
+async fn handshake_inner<T>(
+    mut stream: T,
+    uri: &Uri,
+    config: &Config,
+) -> ConnectResult<(T, Response<()>)>
 where
     T: AsyncRead + AsyncWrite,
 {
@@ -90,52 +459,91 @@ where
     let key = BASE64_STANDARD.encode(key_bytes);
 
     // Create the HTTP request for the handshake.
-    let request = http_request(uri, &key);
+    let request = http_request(uri, config.server_name.as_deref(), &key)?;
 
     // Send the handshake request.
     let BufResult(result, _) = stream.write_all(request.into_bytes()).await;
     result?;
 
     // Read the response.
-    let mut response = String::with_capacity(2048);
-    loop {
-        let line = read_line(&mut stream).await?;
-        response.push_str(&line);
-        // Empty line signals end of headers.
-        if line == "\r\n" {
-            break;
-        }
-    }
+    let response = read_response(&mut stream).await?;
 
-    // Verify the response status.
-    if !response.starts_with("HTTP/1.1 101") {
-        return Err(ConnectError::InvalidHandshakeResponse(response));
+    // Verify the response status. Keep the body around so that the caller can
+    // tell why the upgrade was refused.
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        let body = read_body(&mut stream, &response).await.unwrap_or_default();
+        let (parts, ()) = response.into_parts();
+        return Err(ConnectError::InvalidHandshakeResponse(Box::new(
+            Response::from_parts(parts, body),
+        )));
     }
 
     // Verify the server's accept key.
-    let expected_accept = {
-        let mut hasher = Sha1::new();
-        hasher.update(format!("{key}258EAFA5-E914-47DA-95CA-C5AB0DC85B11").as_bytes());
-        BASE64_STANDARD.encode(hasher.finalize())
-    };
-    if !response
-        .to_lowercase()
-        .contains(&format!("Sec-WebSocket-Accept: {expected_accept}").to_lowercase())
+    let expected_accept = accept_key(&key);
+    if response
+        .headers()
+        .get(SEC_WEBSOCKET_ACCEPT)
+        .is_none_or(|accept| accept.as_bytes() != expected_accept.as_bytes())
     {
         return Err(ConnectError::InvalidWebSocketAcceptHeader);
     }
 
-    Ok(stream)
+    Ok((stream, response))
+}
+
+/// Derives the `Sec-WebSocket-Accept` value the server must answer `key` with.
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("{key}258EAFA5-E914-47DA-95CA-C5AB0DC85B11").as_bytes());
+    BASE64_STANDARD.encode(hasher.finalize())
+}
+
+/// Returns the host of `uri` with the brackets around IPv6 literals removed.
+pub(crate) fn host(uri: &Uri) -> ConnectResult<&str> {
+    let host = uri
+        .host()
+        .filter(|host| !host.is_empty())
+        .ok_or(ConnectError::MissingHost)?;
+    Ok(host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host))
+}
+
+/// Returns the port of `uri`, falling back to the default port of its scheme.
+pub(crate) fn port(uri: &Uri) -> u16 {
+    uri.port_u16().unwrap_or_else(|| default_port(uri))
+}
+
+fn default_port(uri: &Uri) -> u16 {
+    match uri.scheme_str() {
+        Some("wss" | "https") => 443,
+        _ => 80,
+    }
 }
 
-fn http_request(uri: &Uri, key: &str) -> String {
-    let host = if let Some(port) = uri.port_u16() {
-        format!("{}:{port}", uri.host().unwrap_or_default())
+/// Formats `host` and `port` as `host:port`, bracketing IPv6 literals.
+pub(crate) fn authority(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
     } else {
-        uri.host().unwrap_or_default().to_string()
+        format!("{host}:{port}")
+    }
+}
+
+fn http_request(uri: &Uri, host: Option<&str>, key: &str) -> ConnectResult<String> {
+    let host = match host {
+        Some(host) => host,
+        None => self::host(uri)?,
+    };
+    // The port is only included if it differs from the default port.
+    let host = match uri.port_u16() {
+        Some(port) if port != default_port(uri) => authority(host, port),
+        _ if host.contains(':') => format!("[{host}]"),
+        _ => host.to_string(),
     };
 
-    format!(
+    Ok(format!(
         "GET {} HTTP/1.1\r\n\
          Host: {host}\r\n\
          Upgrade: websocket\r\n\
@@ -143,13 +551,11 @@ fn http_request(uri: &Uri, key: &str) -> String {
          Sec-WebSocket-Key: {key}\r\n\
          Sec-WebSocket-Version: 13\r\n\
          \r\n",
-        uri.path_and_query()
-            .map(ToString::to_string)
-            .unwrap_or_default(),
-    )
+        uri.path_and_query().map_or("/", PathAndQuery::as_str),
+    ))
 }
 
-async fn read_line<T>(stream: &mut T) -> io::Result<String>
+pub(crate) async fn read_line<T>(stream: &mut T) -> io::Result<String>
 where
     T: AsyncRead,
 {
@@ -172,16 +578,204 @@ where
     String::from_utf8(line).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid UTF-8"))
 }
 
+pub(crate) async fn read_response<T>(stream: &mut T) -> ConnectResult<Response<()>>
+where
+    T: AsyncRead,
+{
+    let mut head = String::with_capacity(2048);
+    loop {
+        let line = read_line(stream).await?;
+        head.push_str(&line);
+        // Empty line signals end of headers.
+        if line == "\r\n" {
+            break;
+        }
+    }
+    parse_response(&head)
+}
+
+fn parse_response(head: &str) -> ConnectResult<Response<()>> {
+    let mut lines = head.split("\r\n");
+
+    let mut status_line = lines.next().unwrap_or_default().splitn(3, ' ');
+    let version = match status_line.next() {
+        Some("HTTP/1.1") => Version::HTTP_11,
+        Some("HTTP/1.0") => Version::HTTP_10,
+        _ => {
+            return Err(ConnectError::MalformedHandshakeResponse(
+                "Unsupported HTTP version.",
+            ));
+        }
+    };
+    let status = status_line
+        .next()
+        .and_then(|status| StatusCode::from_bytes(status.as_bytes()).ok())
+        .ok_or(ConnectError::MalformedHandshakeResponse(
+            "Invalid status code.",
+        ))?;
+
+    let mut builder = Response::builder().version(version).status(status);
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let (name, value) =
+            line.split_once(':')
+                .ok_or(ConnectError::MalformedHandshakeResponse(
+                    "Header without a colon.",
+                ))?;
+        builder = builder.header(name.trim(), value.trim());
+    }
+    builder
+        .body(())
+        .map_err(|_| ConnectError::MalformedHandshakeResponse("Invalid header."))
+}
+
+pub(crate) async fn read_body<T>(stream: &mut T, response: &Response<()>) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead,
+{
+    let len = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<usize>().ok())
+        .unwrap_or_default()
+        .min(MAX_RESPONSE_BODY_LEN);
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+
+    let BufResult(result, body) = stream.read_exact(Vec::with_capacity(len)).await;
+    result?;
+    Ok(body)
+}
+
+/// Reads a handshake request from `stream` and accepts it. Returns the
+/// request head.
+#[cfg(test)]
+pub(crate) async fn accept_handshake<T>(stream: &mut T) -> String
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let mut request = String::new();
+    let mut key = String::new();
+    loop {
+        let line = read_line(stream).await.unwrap();
+        if let Some(value) = line.strip_prefix("Sec-WebSocket-Key: ") {
+            key = value.trim_end().to_string();
+        }
+        request.push_str(&line);
+        if line == "\r\n" {
+            break;
+        }
+    }
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Accept: {}\r\n\
+        Session-Id: 42\r\n\
+        \r\n",
+        accept_key(&key)
+    );
+    let BufResult(result, _) = stream.write_all(response).await;
+    result.unwrap();
+    request
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use compio::net::{TcpListener, UnixListener};
+    use test_case::test_case;
+
     use super::*;
 
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455 section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[compio::test]
+    async fn test_connect_with_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = compio::runtime::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            accept_handshake(&mut stream).await;
+        });
+
+        let uri = Uri::from_static("ws://stand-in/stream");
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let client = Client::connect_with_stream(stream, &uri, &Config::default())
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        assert_eq!(client.uri(), Some(&uri));
+        let response = client.response().unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(response.headers()["session-id"], "42");
+    }
+
+    #[compio::test]
+    async fn test_plain_redirect_to_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        compio::runtime::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while read_line(&mut stream).await.unwrap() != "\r\n" {}
+            let BufResult(result, _) = stream
+                .write_all(
+                    "HTTP/1.1 301 Moved Permanently\r\n\
+                     Location: wss://example.com/stream\r\n\
+                     Content-Length: 0\r\n\r\n",
+                )
+                .await;
+            result.unwrap();
+        })
+        .detach();
+
+        let config = Config {
+            max_redirects: 1,
+            ..Config::default()
+        };
+        let uri = format!("ws://{addr}/stream").parse().unwrap();
+        let Err(ConnectError::SchemeChangingRedirect(redirect)) =
+            Client::connect_plain(&uri, &config).await
+        else {
+            panic!("expected the redirect to wss:// to be refused");
+        };
+        assert_eq!(redirect, "wss://example.com/stream");
+    }
+
+    #[compio::test]
+    async fn test_handshake_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        // Accept the connection but never answer the upgrade request.
+        let (_server_stream, _) = listener.accept().await.unwrap();
+
+        let config = Config {
+            handshake_timeout: Some(Duration::from_millis(50)),
+            ..Config::default()
+        };
+        let uri = Uri::from_static("ws://stand-in/stream");
+        let res = Client::connect_with_stream(stream, &uri, &config).await;
+        assert!(matches!(res, Err(ConnectError::HandshakeTimeout)));
+    }
+
     #[test]
     fn test_http_request() {
         let output = http_request(
             &Uri::from_static("ws://localhost:9001/runCase?case=1&agent=monoio-ws"),
+            None,
             "dGhlIHNhbXBsZSBub25jZQ==",
-        );
+        )
+        .unwrap();
         assert_eq!(
             output,
             "GET /runCase?case=1&agent=monoio-ws HTTP/1.1\r\n\
@@ -193,4 +787,172 @@ mod tests {
             \r\n"
         )
     }
+
+    #[test]
+    fn test_http_request_with_host_override() {
+        let output = http_request(
+            &Uri::from_static("wss://10.0.0.1:8443/stream"),
+            Some("stream.example.com"),
+            "dGhlIHNhbXBsZSBub25jZQ==",
+        )
+        .unwrap();
+        assert!(output.contains("\r\nHost: stream.example.com:8443\r\n"));
+    }
+
+    #[test_case("ws://[::1]:9001/" => "[::1]:9001"; "ipv6 with port")]
+    #[test_case("wss://[2001:db8::1]/" => "[2001:db8::1]"; "ipv6 without port")]
+    #[test_case("ws://example.com:80/" => "example.com"; "default ws port")]
+    #[test_case("wss://example.com:443/" => "example.com"; "default wss port")]
+    #[test_case("wss://example.com:80/" => "example.com:80"; "non-default wss port")]
+    fn test_http_request_host(uri: &str) -> String {
+        let request = http_request(&uri.parse().unwrap(), None, "key").unwrap();
+        request
+            .lines()
+            .find_map(|line| line.strip_prefix("Host: "))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test_case("ws://[::1]:9001/" => ("::1", 9001); "ipv6")]
+    #[test_case("ws://127.0.0.1/" => ("127.0.0.1", 80); "ipv4 default port")]
+    #[test_case("wss://example.com/" => ("example.com", 443); "domain default port")]
+    fn test_host_and_port(uri: &str) -> (&'static str, u16) {
+        let uri = uri.parse::<Uri>().unwrap();
+        let host = host(&uri).unwrap().to_string().leak();
+        (host, port(&uri))
+    }
+
+    #[test]
+    fn test_missing_host() {
+        assert!(matches!(
+            host(&Uri::from_static("/path")),
+            Err(ConnectError::MissingHost)
+        ));
+        assert!(matches!(
+            http_request(&Uri::from_static("/path"), None, "key"),
+            Err(ConnectError::MissingHost)
+        ));
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = parse_response(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+            X-RateLimit-Remaining: 42\r\n\
+            \r\n",
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(response.version(), Version::HTTP_11);
+        assert_eq!(
+            response.headers()[SEC_WEBSOCKET_ACCEPT],
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "42");
+    }
+
+    #[test_case("HTTP/2 101 Switching Protocols\r\n\r\n"; "unsupported version")]
+    #[test_case("HTTP/1.1 abc Switching Protocols\r\n\r\n"; "invalid status")]
+    #[test_case("HTTP/1.1 101 Switching Protocols\r\nUpgrade\r\n\r\n"; "header without colon")]
+    #[test_case("HTTP/1.1 101 Switching Protocols\r\nUp grade: websocket\r\n\r\n"; "invalid header name")]
+    fn test_parse_malformed_response(head: &str) {
+        assert!(matches!(
+            parse_response(head),
+            Err(ConnectError::MalformedHandshakeResponse(_))
+        ));
+    }
+
+    #[test_case("wss://example.com/ws", "wss://example.com/ws"; "absolute")]
+    #[test_case("https://eu.example.com/ws?x=1", "wss://eu.example.com/ws?x=1"; "https to wss")]
+    #[test_case("http://eu.example.com:8080", "ws://eu.example.com:8080/"; "http to ws")]
+    #[test_case("/v2/stream", "ws://example.com:9001/v2/stream"; "relative path")]
+    fn test_redirect_uri(location: &str, expected: &str) {
+        let response = Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header(LOCATION, location)
+            .body(Vec::new())
+            .unwrap();
+        let uri = redirect_uri(&Uri::from_static("ws://example.com:9001/stream"), &response);
+        assert_eq!(uri.unwrap(), Uri::try_from(expected).unwrap());
+    }
+
+    #[test_case("http://eu.example.com/ws"; "http")]
+    #[test_case("ws://eu.example.com/ws"; "ws")]
+    fn test_insecure_redirect_uri(location: &str) {
+        let response = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(LOCATION, location)
+            .body(Vec::new())
+            .unwrap();
+        assert!(matches!(
+            redirect_uri(&Uri::from_static("wss://example.com/"), &response),
+            Err(ConnectError::InsecureRedirect)
+        ));
+    }
+
+    #[test_case(None; "missing")]
+    #[test_case(Some("ftp://example.com/"); "unsupported scheme")]
+    fn test_invalid_redirect_uri(location: Option<&str>) {
+        let mut response = Response::builder().status(StatusCode::FOUND);
+        if let Some(location) = location {
+            response = response.header(LOCATION, location);
+        }
+        let response = response.body(Vec::new()).unwrap();
+        assert!(matches!(
+            redirect_uri(&Uri::from_static("ws://example.com/"), &response),
+            Err(ConnectError::InvalidRedirectLocation)
+        ));
+    }
+
+    #[compio::test]
+    async fn test_connect_unix() {
+        let path = std::env::temp_dir().join(format!("compio-ws-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).await.unwrap();
+        let server = compio::runtime::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            accept_handshake(&mut stream).await
+        });
+
+        let config = Config {
+            server_name: Some("sidecar".to_string()),
+            ..Default::default()
+        };
+        let uri = Uri::from_static("ws://localhost/v1/events");
+        Client::connect_unix(&path, &uri, &config).await.unwrap();
+        let request = server.await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(request.starts_with("GET /v1/events HTTP/1.1\r\nHost: sidecar\r\n"));
+    }
+
+    #[test_case(StatusCode::TOO_MANY_REQUESTS => true; "too many requests")]
+    #[test_case(StatusCode::SERVICE_UNAVAILABLE => true; "service unavailable")]
+    #[test_case(StatusCode::UNAUTHORIZED => false; "unauthorized")]
+    #[test_case(StatusCode::NOT_FOUND => false; "not found")]
+    fn test_handshake_status_is_transient(status: StatusCode) -> bool {
+        let response = Response::builder().status(status).body(Vec::new()).unwrap();
+        ConnectError::InvalidHandshakeResponse(Box::new(response)).is_transient()
+    }
+
+    #[test_case(ConnectError::Io(io::ErrorKind::ConnectionRefused.into()) => true; "connection refused")]
+    #[test_case(ConnectError::Io(io::ErrorKind::PermissionDenied.into()) => false; "permission denied")]
+    #[test_case(ConnectError::Socks5Reply(0x04) => true; "socks5 host unreachable")]
+    #[test_case(ConnectError::Socks5Reply(0x02) => false; "socks5 not allowed")]
+    #[test_case(ConnectError::HandshakeTimeout => true; "handshake timeout")]
+    #[test_case(ConnectError::InvalidUriScheme => false; "invalid scheme")]
+    fn test_connect_error_is_transient(err: ConnectError) -> bool {
+        err.is_transient()
+    }
+
+    #[cfg(feature = "rustls")]
+    #[test]
+    fn test_pin_mismatch_is_certificate_failure() {
+        let err = ConnectError::CertificatePinMismatch;
+        assert!(err.is_certificate_failure());
+        assert!(!err.is_transient());
+    }
 }
@@ -0,0 +1,150 @@
+//! Connection racing over all resolved addresses, as described in
+//! [RFC 8305](https://www.rfc-editor.org/rfc/rfc8305).
+
+use std::{io, net::SocketAddr, pin::pin, time::Duration};
+
+use compio::{net::TcpStream, time};
+use futures_util::{
+    StreamExt,
+    future::{self, Either},
+    stream::FuturesUnordered,
+};
+
+use crate::{Config, SocketConfig, resolver};
+
+/// Resolves `host` with [`Config::resolver`] and connects to whichever of its addresses accepts first.
+///
+/// Attempts are started one [`Config::connect_attempt_delay`] apart, or as
+/// soon as the previous one fails, alternating between IPv6 and IPv4
+/// addresses. Each attempt is bounded by [`Config::connect_attempt_timeout`].
+/// Only addresses of the family of [`SocketConfig::bind_addr`] are tried if it
+/// is set.
+pub(crate) async fn connect(host: &str, port: u16, config: &Config) -> io::Result<TcpStream> {
+    let mut addrs = resolver::resolve(config.resolver.as_deref(), host, port).await?;
+    if let Some(bind_addr) = config.socket.bind_addr {
+        addrs.retain(|addr| addr.is_ipv6() == bind_addr.is_ipv6());
+    }
+    race(interleave(addrs), config).await
+}
+
+/// Error returned when a connection attempt or the whole connect runs out of
+/// time.
+pub(crate) fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "connect timed out")
+}
+
+async fn race(addrs: Vec<SocketAddr>, config: &Config) -> io::Result<TcpStream> {
+    let attempt = |addr| attempt(addr, &config.socket, config.connect_attempt_timeout);
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    attempts.extend(addrs.next().map(attempt));
+
+    let mut last_err = None;
+    while !attempts.is_empty() {
+        let next = if addrs.len() == 0 {
+            attempts.next().await
+        } else {
+            let delay = pin!(time::sleep(config.connect_attempt_delay));
+            match future::select(attempts.next(), delay).await {
+                Either::Left((next, _)) => next,
+                Either::Right(((), _)) => None,
+            }
+        };
+        match next {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(err)) => last_err = Some(err),
+            // The attempt delay elapsed without any attempt finishing.
+            None => {}
+        }
+        attempts.extend(addrs.next().map(attempt));
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "host resolved to no addresses")
+    }))
+}
+
+async fn attempt(
+    addr: SocketAddr,
+    socket: &SocketConfig,
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, socket.connect(addr))
+            .await
+            .unwrap_or_else(|_| Err(timed_out())),
+        None => socket.connect(addr).await,
+    }
+}
+
+/// Alternates between address families, starting with the family of the
+/// address the resolver preferred.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let len = addrs.len();
+    let prefer_ipv6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == prefer_ipv6);
+
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    let mut interleaved = Vec::with_capacity(len);
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return interleaved,
+            (first, second) => interleaved.extend(first.into_iter().chain(second)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compio::net::TcpListener;
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(&[] => Vec::<&str>::new(); "empty")]
+    #[test_case(
+        &["[::1]:80", "[::2]:80", "127.0.0.1:80"] => vec!["[::1]:80", "127.0.0.1:80", "[::2]:80"];
+        "ipv6 preferred"
+    )]
+    #[test_case(
+        &["127.0.0.1:80", "127.0.0.2:80", "[::1]:80"] => vec!["127.0.0.1:80", "[::1]:80", "127.0.0.2:80"];
+        "ipv4 preferred"
+    )]
+    #[test_case(
+        &["127.0.0.1:80", "127.0.0.2:80"] => vec!["127.0.0.1:80", "127.0.0.2:80"];
+        "single family"
+    )]
+    fn test_interleave(addrs: &[&str]) -> Vec<String> {
+        let addrs = addrs.iter().map(|addr| addr.parse().unwrap()).collect();
+        interleave(addrs).iter().map(ToString::to_string).collect()
+    }
+
+    #[compio::test]
+    async fn test_race_skips_refused_address() {
+        let refused_addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // A long attempt delay makes sure the second attempt is started by the
+        // first one failing rather than by the delay elapsing.
+        let config = Config {
+            connect_attempt_delay: Duration::from_secs(60),
+            ..Config::default()
+        };
+        let stream = race(vec![refused_addr, addr], &config).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+    }
+
+    #[compio::test]
+    async fn test_race_without_addresses() {
+        let err = race(Vec::new(), &Config::default()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
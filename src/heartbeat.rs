@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+use crate::Config;
+
+/// Keepalive pings sent after [`Config::ping_interval`] of outbound idleness.
+/// The ping counts as answered as soon as any data arrives.
+pub(crate) struct Heartbeat {
+    interval: Duration,
+    pong_timeout: Duration,
+    last_write: Instant,
+    ping_sent: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(config: &Config) -> Option<Self> {
+        config.ping_interval.map(|interval| Self {
+            interval,
+            pong_timeout: config.pong_timeout,
+            last_write: Instant::now(),
+            ping_sent: None,
+        })
+    }
+
+    /// Whether a ping has to be sent.
+    pub(crate) fn ping_due(&self, now: Instant) -> bool {
+        self.ping_sent.is_none() && now >= self.last_write + self.interval
+    }
+
+    /// Time by which data has to arrive for the outstanding ping, or else when
+    /// the next ping falls due.
+    pub(crate) fn deadline(&self) -> Instant {
+        self.pong_deadline()
+            .unwrap_or(self.last_write + self.interval)
+    }
+
+    /// Time by which data has to arrive for the outstanding ping, if any.
+    pub(crate) fn pong_deadline(&self) -> Option<Instant> {
+        self.ping_sent.map(|sent| sent + self.pong_timeout)
+    }
+
+    pub(crate) fn on_write(&mut self, now: Instant) {
+        self.last_write = now;
+    }
+
+    pub(crate) fn on_ping(&mut self, now: Instant) {
+        self.ping_sent = Some(now);
+    }
+
+    pub(crate) fn on_read(&mut self) {
+        self.ping_sent = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compio::{
+        BufResult,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        time,
+    };
+    use futures_util::future;
+
+    use super::*;
+    use crate::{Client, Error, Opcode};
+
+    fn heartbeat() -> Heartbeat {
+        Heartbeat::new(&Config {
+            ping_interval: Some(Duration::from_secs(10)),
+            pong_timeout: Duration::from_secs(5),
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        assert!(Heartbeat::new(&Config::default()).is_none());
+    }
+
+    #[test]
+    fn test_ping_after_outbound_idleness() {
+        let mut heartbeat = heartbeat();
+        let start = Instant::now();
+        heartbeat.on_write(start);
+
+        assert!(!heartbeat.ping_due(start + Duration::from_secs(9)));
+        assert!(heartbeat.ping_due(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_pong_deadline() {
+        let mut heartbeat = heartbeat();
+        let start = Instant::now();
+        heartbeat.on_write(start);
+        assert_eq!(heartbeat.pong_deadline(), None);
+
+        heartbeat.on_ping(start);
+        assert_eq!(
+            heartbeat.pong_deadline(),
+            Some(start + Duration::from_secs(5))
+        );
+        // Only one ping is outstanding at a time.
+        assert!(!heartbeat.ping_due(start + Duration::from_secs(10)));
+
+        heartbeat.on_read();
+        assert_eq!(heartbeat.pong_deadline(), None);
+        assert!(heartbeat.ping_due(start + Duration::from_secs(10)));
+    }
+
+    async fn connect(ping_interval: Duration) -> (Client<TcpStream>, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server_stream, _) = listener.accept().await.unwrap();
+        let client = Client::new(
+            stream,
+            &Config {
+                ping_interval: Some(ping_interval),
+                pong_timeout: Duration::from_millis(50),
+                ..Config::default()
+            },
+        );
+        (client, server_stream)
+    }
+
+    #[compio::test]
+    async fn test_client_pong_timeout() {
+        let (mut client, mut server_stream) = connect(Duration::from_millis(10)).await;
+
+        // The read starts before the ping is due and the server stays silent,
+        // so the ping has to be sent while the read is pending.
+        let start = Instant::now();
+        assert!(matches!(client.read_frame().await, Err(Error::PongTimeout)));
+        assert!(start.elapsed() >= Duration::from_millis(60));
+
+        // A masked ping with the RTT payload.
+        let BufResult(result, header) = server_stream.read_exact(Vec::with_capacity(2)).await;
+        result.unwrap();
+        assert_eq!(header, [0x89, 0x80 | 16]);
+    }
+
+    #[compio::test]
+    async fn test_data_at_ping_deadline() {
+        let ping_interval = Duration::from_millis(50);
+        let (mut client, mut server_stream) = connect(ping_interval).await;
+
+        let server = async {
+            time::sleep(ping_interval).await;
+            let BufResult(result, _) = server_stream.write_all(vec![0x82, 0x01, 0xAA]).await;
+            result.unwrap();
+            let BufResult(result, header) = server_stream.read_exact(Vec::with_capacity(2)).await;
+            result.unwrap();
+            assert_eq!(header, [0x89, 0x80 | 16]);
+            let BufResult(result, _) = server_stream.write_all(vec![0x82, 0x01, 0xBB]).await;
+            result.unwrap();
+        };
+        let reads = async {
+            // The read pending when the ping falls due is not cancelled to
+            // send the ping, so the frame arriving at that moment is not lost.
+            let frame = client.read_frame().await.unwrap();
+            assert_eq!((frame.opcode, frame.data), (Opcode::Binary, &[0xAA][..]));
+            // The ping goes out during this read or the previous one.
+            let frame = client.read_frame().await.unwrap();
+            assert_eq!((frame.opcode, frame.data), (Opcode::Binary, &[0xBB][..]));
+        };
+        future::join(reads, server).await;
+    }
+}
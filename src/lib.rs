@@ -2,6 +2,22 @@ mod client;
 mod close_code;
 mod connect;
 mod frame;
+mod happy_eyeballs;
+mod heartbeat;
 mod opcode;
+mod proxy;
+mod reconnect;
+mod redundant;
+mod resolver;
+mod rtt;
+mod socket;
+mod stream;
+#[cfg(feature = "rustls")]
+mod tls;
 
-pub use self::{client::*, close_code::*, connect::*, frame::*, opcode::*};
+#[cfg(feature = "rustls")]
+pub use self::tls::*;
+pub use self::{
+    client::*, close_code::*, connect::*, frame::*, opcode::*, proxy::*, reconnect::*,
+    redundant::*, resolver::*, rtt::*, socket::*, stream::*,
+};
@@ -0,0 +1,656 @@
+use std::{
+    pin::{Pin, pin},
+    str,
+    time::{Duration, Instant},
+};
+
+use compio::time;
+use futures_util::future::{self, Either};
+use http::Uri;
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+use crate::{Client, CloseCode, Config, Error, Frame, MaybeTlsStream, Opcode, Result};
+
+/// Delay between reconnect attempts, doubling with every consecutive failure.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    /// Delay before the first reconnect attempt.
+    pub initial_delay: Duration,
+    /// Upper bound on the delay between attempts.
+    pub max_delay: Duration,
+    /// Number of consecutive failed attempts after which the last error is
+    /// returned. Reconnects are attempted indefinitely by default.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Returns the delay after `failures` consecutive failures, randomized
+    /// between half and all of the exponential delay so clients don't
+    /// reconnect in lockstep.
+    fn delay(&self, failures: u32, rng: &mut impl Rng) -> Duration {
+        let delay = self
+            .initial_delay
+            .saturating_mul(1 << failures.saturating_sub(1).min(31))
+            .min(self.max_delay);
+        rng.random_range(delay / 2..=delay)
+    }
+}
+
+/// When [`ReconnectingClient`] switches back to its first endpoint after
+/// failing over, see [`ReconnectingClient::with_failback`].
+#[derive(Clone, Debug)]
+pub struct Failback {
+    /// How long the first endpoint has to have been healthy, i.e. every probe
+    /// of it succeeded, before switching back to it.
+    pub after: Duration,
+    /// Delay between probes of the first endpoint.
+    pub probe_interval: Duration,
+    /// Time limit on a probe, and on opening the connection that replaces the
+    /// current one, including [`ReconnectHandler`] hooks. Probes run while
+    /// frames are read, but reading frames pauses for at most this long while
+    /// the replacement is opened.
+    pub probe_timeout: Duration,
+}
+
+impl Default for Failback {
+    fn default() -> Self {
+        Self {
+            after: Duration::from_secs(60),
+            probe_interval: Duration::from_secs(10),
+            probe_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Hooks called by [`ReconnectingClient`] around every connection.
+///
+/// Implementations can use `async fn` for the async hooks.
+pub trait ReconnectHandler {
+    /// Called after every handshake to authenticate the connection. An error
+    /// counts as a failed attempt.
+    fn authenticate(
+        &mut self,
+        _client: &mut Client<MaybeTlsStream>,
+    ) -> impl Future<Output = Result<()>> {
+        async { Ok(()) }
+    }
+
+    /// Called after [`ReconnectHandler::authenticate`] to send the
+    /// subscriptions again. An error counts as a failed attempt.
+    fn subscribe(
+        &mut self,
+        _client: &mut Client<MaybeTlsStream>,
+    ) -> impl Future<Output = Result<()>> {
+        async { Ok(()) }
+    }
+
+    /// Called whenever the connection is lost or an attempt fails, before
+    /// waiting for the next attempt.
+    fn on_disconnect(&mut self, _error: &Error) {}
+}
+
+impl ReconnectHandler for () {}
+
+/// A client that transparently reconnects whenever the connection fails, the
+/// server closes it or reading from it fails, including on protocol errors and
+/// timeouts.
+///
+/// Reconnects are delayed according to [`Backoff`], except after the server
+/// closed the connection with [`CloseCode::ServiceRestart`] or
+/// [`CloseCode::TryAgainLater`], where a random delay of 5-30 seconds is used
+/// as recommended. Close frames are answered and handled internally, all other
+/// frames are returned from [`ReconnectingClient::read_frame`].
+///
+/// With several endpoints, see [`ReconnectingClient::with_endpoints`], the
+/// client fails over to the next endpoint whenever connecting or the handshake
+/// fails or the connection times out. Reconnects are only delayed once all
+/// endpoints have been tried.
+pub struct ReconnectingClient<H> {
+    endpoints: Vec<Uri>,
+    endpoint: usize,
+    failback: Option<Failback>,
+    next_probe: Instant,
+    probe: Option<Pin<Box<dyn Future<Output = bool>>>>,
+    healthy_since: Option<Instant>,
+    config: Config,
+    backoff: Backoff,
+    handler: H,
+    client: Option<Client<MaybeTlsStream>>,
+    failures: u32,
+    delay: Option<Duration>,
+    rng: SmallRng,
+}
+
+impl<H> ReconnectingClient<H>
+where
+    H: ReconnectHandler,
+{
+    /// Creates a client for `uri`. The first connection is opened by the first
+    /// call to [`ReconnectingClient::read_frame`] or
+    /// [`ReconnectingClient::connect`].
+    pub fn new(uri: Uri, config: Config, backoff: Backoff, handler: H) -> Self {
+        Self::with_endpoints(vec![uri], config, backoff, handler)
+    }
+
+    /// Creates a client for an ordered list of endpoints, e.g. primary,
+    /// secondary and disaster recovery. The first endpoint is connected to
+    /// first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `endpoints` is empty.
+    pub fn with_endpoints(
+        endpoints: Vec<Uri>,
+        config: Config,
+        backoff: Backoff,
+        handler: H,
+    ) -> Self {
+        assert!(!endpoints.is_empty(), "at least one endpoint is required");
+        Self {
+            endpoints,
+            endpoint: 0,
+            failback: None,
+            next_probe: Instant::now(),
+            probe: None,
+            healthy_since: None,
+            config,
+            backoff,
+            handler,
+            client: None,
+            failures: 0,
+            delay: None,
+            rng: SmallRng::from_os_rng(),
+        }
+    }
+
+    /// Switches back to the first endpoint once it has been healthy for
+    /// [`Failback::after`] while connected to another one. The first endpoint
+    /// is probed with a handshake every [`Failback::probe_interval`] while
+    /// frames are read, and any failed probe starts the wait over.
+    ///
+    /// Frames the previous connection received but that were not read yet are
+    /// dropped when switching, so messages around the switch can be lost.
+    #[must_use]
+    pub fn with_failback(mut self, failback: Failback) -> Self {
+        self.failback = Some(failback);
+        self
+    }
+
+    /// Returns the endpoint that is or will be connected to.
+    #[must_use]
+    pub fn endpoint(&self) -> &Uri {
+        &self.endpoints[self.endpoint]
+    }
+
+    /// Returns the current connection, e.g. to send messages over it, or
+    /// `None` while disconnected.
+    pub fn client(&mut self) -> Option<&mut Client<MaybeTlsStream>> {
+        self.client.as_mut()
+    }
+
+    pub fn handler(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Connects unless already connected, retrying according to [`Backoff`].
+    pub async fn connect(&mut self) -> Result<&mut Client<MaybeTlsStream>> {
+        if self.client.is_none() {
+            self.reconnect().await?;
+        }
+        Ok(self.client.as_mut().expect("connected"))
+    }
+
+    /// Reads the next frame, reconnecting as often as needed. Fails only once
+    /// [`Backoff::max_attempts`] consecutive attempts failed.
+    pub async fn read_frame(&mut self) -> Result<Frame<'_>> {
+        loop {
+            self.fail_back().await;
+            self.connect().await?;
+            let client = self.client.as_mut().expect("connected");
+            let res = match self.probe.as_mut() {
+                // The probe is kept when the read completes first, so that it
+                // can finish during the next reads.
+                Some(probe) => match future::select(probe, pin!(client.read_frame_inner())).await {
+                    Either::Left((healthy, read)) => {
+                        self.probe = None;
+                        if !healthy {
+                            self.healthy_since = None;
+                        }
+                        read.await
+                    }
+                    Either::Right((res, _)) => res,
+                },
+                None => client.read_frame_inner().await,
+            };
+            let err = match res {
+                Ok(frame) if frame.opcode == Opcode::Close => {
+                    let data = client.buffered_frame(frame).data;
+                    let (code, reason) = parse_close(data);
+                    let code_bytes = data.get(..2).map(<[u8]>::to_vec).unwrap_or_default();
+                    // The connection is dropped right after, so failing to
+                    // answer the close frame doesn't matter.
+                    let _ = client.send_close(&code_bytes).await;
+                    Error::Closed { code, reason }
+                }
+                Ok(frame) => {
+                    self.failures = 0;
+                    let client = self.client.as_ref().expect("connected");
+                    return Ok(client.buffered_frame(frame));
+                }
+                Err(err) => err,
+            };
+            self.client = None;
+            self.on_failure(&err);
+        }
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        loop {
+            if let Some(delay) = self.delay.take() {
+                time::sleep(delay).await;
+            }
+            match self.open(self.endpoint).await {
+                Ok(client) => {
+                    self.client = Some(client);
+                    self.probe = None;
+                    self.healthy_since = None;
+                    if let Some(failback) = &self.failback {
+                        self.next_probe = Instant::now() + failback.probe_interval;
+                    }
+                    return Ok(());
+                }
+                Err(err) => {
+                    if self
+                        .backoff
+                        .max_attempts
+                        .is_some_and(|max_attempts| self.failures + 1 >= max_attempts)
+                    {
+                        return Err(err);
+                    }
+                    self.on_failure(&err);
+                }
+            }
+        }
+    }
+
+    /// Starts a probe of the first endpoint if due, which [`Self::read_frame`]
+    /// then drives alongside the reads, and replaces the connection with one to
+    /// the first endpoint once it has been healthy for long enough, see
+    /// [`ReconnectingClient::with_failback`].
+    async fn fail_back(&mut self) {
+        let Some(failback) = self.failback.clone() else {
+            return;
+        };
+        let now = Instant::now();
+        if self.endpoint == 0
+            || self.client.is_none()
+            || self.probe.is_some()
+            || now < self.next_probe
+        {
+            return;
+        }
+        self.next_probe = now + failback.probe_interval;
+
+        let healthy_since = *self.healthy_since.get_or_insert(now);
+        if now.duration_since(healthy_since) < failback.after {
+            let uri = self.endpoints[0].clone();
+            let config = self.config.clone();
+            self.probe = Some(Box::pin(async move {
+                let probe = Client::connect(&uri, &config);
+                match time::timeout(failback.probe_timeout, probe).await {
+                    Ok(Ok(mut probe)) => {
+                        let _ = probe.send_close(&close_normal()).await;
+                        true
+                    }
+                    _ => false,
+                }
+            }));
+            return;
+        }
+
+        // Either way the wait starts over: after switching back or because
+        // the first endpoint failed.
+        self.healthy_since = None;
+        if let Ok(Ok(client)) = time::timeout(failback.probe_timeout, self.open(0)).await {
+            if let Some(mut previous) = self.client.replace(client) {
+                let _ = previous.send_close(&close_normal()).await;
+            }
+            self.endpoint = 0;
+        }
+    }
+
+    async fn open(&mut self, endpoint: usize) -> Result<Client<MaybeTlsStream>> {
+        let mut client = Client::connect(&self.endpoints[endpoint], &self.config).await?;
+        self.handler.authenticate(&mut client).await?;
+        self.handler.subscribe(&mut client).await?;
+        Ok(client)
+    }
+
+    fn on_failure(&mut self, err: &Error) {
+        self.failures += 1;
+        self.delay = if is_failover_error(err) && self.endpoints.len() > 1 {
+            self.endpoint = (self.endpoint + 1) % self.endpoints.len();
+            // Only back off once every endpoint has been tried.
+            (self.endpoint == 0).then(|| self.delay_after(err))
+        } else {
+            Some(self.delay_after(err))
+        };
+        self.handler.on_disconnect(err);
+    }
+
+    fn delay_after(&mut self, err: &Error) -> Duration {
+        match err {
+            Error::Closed {
+                code: Some(CloseCode::ServiceRestart | CloseCode::TryAgainLater),
+                ..
+            } => self
+                .rng
+                .random_range(Duration::from_secs(5)..=Duration::from_secs(30)),
+            _ => self.backoff.delay(self.failures, &mut self.rng),
+        }
+    }
+}
+
+fn close_normal() -> [u8; 2] {
+    u16::from(CloseCode::Normal).to_be_bytes()
+}
+
+/// Whether `err` suggests the endpoint is unreachable or unhealthy rather than
+/// the connection having been lost.
+fn is_failover_error(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Connect(_) | Error::ReadTimeout | Error::PongTimeout
+    )
+}
+
+/// Returns the close code and reason of a close frame's payload.
+pub(crate) fn parse_close(data: &[u8]) -> (Option<CloseCode>, Option<String>) {
+    let Some((code, reason)) = data.split_first_chunk::<2>() else {
+        return (None, None);
+    };
+    let code = CloseCode::try_from(u16::from_be_bytes(*code)).ok();
+    let reason = str::from_utf8(reason)
+        .ok()
+        .filter(|reason| !reason.is_empty())
+        .map(ToString::to_string);
+    (code, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use compio::{
+        BufResult,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use test_case::test_case;
+
+    use super::*;
+    use crate::connect::accept_handshake;
+
+    #[test_case(1 => (50, 100); "first failure")]
+    #[test_case(3 => (200, 400); "third failure")]
+    #[test_case(20 => (500, 1000); "capped")]
+    fn test_backoff_delay(failures: u32) -> (u128, u128) {
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            max_attempts: None,
+        };
+        let mut rng = SmallRng::seed_from_u64(0);
+        let (mut min, mut max) = (u128::MAX, 0);
+        for _ in 0..1000 {
+            let delay = backoff.delay(failures, &mut rng).as_millis();
+            min = min.min(delay);
+            max = max.max(delay);
+        }
+        // Round to the bounds the delays are spread between.
+        (min / 50 * 50, max.div_ceil(50) * 50)
+    }
+
+    #[test_case(&[] => (None, None); "empty")]
+    #[test_case(&[0x03, 0xE8] => (Some(CloseCode::Normal), None); "code only")]
+    #[test_case(b"\x03\xF4restart" => (Some(CloseCode::ServiceRestart), Some("restart".to_string())); "code and reason")]
+    fn test_parse_close(data: &[u8]) -> (Option<CloseCode>, Option<String>) {
+        parse_close(data)
+    }
+
+    #[test]
+    fn test_close_code_delay() {
+        let mut client = ReconnectingClient::new(
+            Uri::from_static("ws://localhost"),
+            Config::default(),
+            Backoff::default(),
+            (),
+        );
+        let delay = client.delay_after(&Error::Closed {
+            code: Some(CloseCode::TryAgainLater),
+            reason: None,
+        });
+        assert!((Duration::from_secs(5)..=Duration::from_secs(30)).contains(&delay));
+    }
+
+    struct Subscriber(Rc<Cell<usize>>);
+
+    impl ReconnectHandler for Subscriber {
+        async fn subscribe(&mut self, client: &mut Client<MaybeTlsStream>) -> Result<()> {
+            self.0.set(self.0.get() + 1);
+            client.send_text(b"subscribe").await
+        }
+    }
+
+    #[compio::test]
+    async fn test_reconnect_after_connection_loss() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        compio::runtime::spawn(async move {
+            // The first connection is dropped right after the handshake.
+            let (mut stream, _) = listener.accept().await.unwrap();
+            accept_handshake(&mut stream).await;
+            drop(stream);
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            accept_handshake(&mut stream).await;
+            // Read the masked subscription before answering it.
+            let BufResult(result, _) = stream.read_exact(Vec::with_capacity(15)).await;
+            result.unwrap();
+            let BufResult(result, _) = stream.write_all(b"\x82\x02hi".to_vec()).await;
+            result.unwrap();
+        })
+        .detach();
+
+        let subscriptions = Rc::new(Cell::new(0));
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(1),
+            ..Backoff::default()
+        };
+        let uri = format!("ws://{addr}/").parse().unwrap();
+        let mut client = ReconnectingClient::new(
+            uri,
+            Config::default(),
+            backoff,
+            Subscriber(subscriptions.clone()),
+        );
+
+        let frame = client.read_frame().await.unwrap();
+        assert_eq!(frame.opcode, Opcode::Binary);
+        assert_eq!(frame.data, b"hi");
+        assert_eq!(subscriptions.get(), 2);
+    }
+
+    #[compio::test]
+    async fn test_failover() {
+        let unreachable_addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        compio::runtime::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            accept_handshake(&mut stream).await;
+            let BufResult(result, _) = stream.write_all(b"\x82\x02hi".to_vec()).await;
+            result.unwrap();
+        })
+        .detach();
+
+        let primary: Uri = format!("ws://{unreachable_addr}/").parse().unwrap();
+        let secondary: Uri = format!("ws://{addr}/").parse().unwrap();
+        let backoff = Backoff {
+            // Failing over must not wait for the backoff.
+            initial_delay: Duration::from_secs(60),
+            ..Backoff::default()
+        };
+        let mut client = ReconnectingClient::with_endpoints(
+            vec![primary, secondary.clone()],
+            Config::default(),
+            backoff,
+            (),
+        );
+
+        let frame = client.read_frame().await.unwrap();
+        assert_eq!(frame.data, b"hi");
+        assert_eq!(client.endpoint(), &secondary);
+    }
+
+    #[compio::test]
+    async fn test_failback() {
+        let primary_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_addr = primary_listener.local_addr().unwrap();
+        let handshakes = Rc::new(Cell::new(0));
+        let primary_handshakes = handshakes.clone();
+        compio::runtime::spawn(async move {
+            // The first handshake fails so the client fails over.
+            drop(primary_listener.accept().await.unwrap());
+            let mut streams = Vec::new();
+            loop {
+                let (mut stream, _) = primary_listener.accept().await.unwrap();
+                accept_handshake(&mut stream).await;
+                primary_handshakes.set(primary_handshakes.get() + 1);
+                let BufResult(result, _) = stream.write_all(b"\x82\x01p".to_vec()).await;
+                result.unwrap();
+                streams.push(stream);
+            }
+        })
+        .detach();
+
+        let secondary_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let secondary_addr = secondary_listener.local_addr().unwrap();
+        compio::runtime::spawn(async move {
+            let (mut stream, _) = secondary_listener.accept().await.unwrap();
+            accept_handshake(&mut stream).await;
+            loop {
+                let BufResult(result, _) = stream.write_all(b"\x82\x01s".to_vec()).await;
+                if result.is_err() {
+                    break;
+                }
+                time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .detach();
+
+        let primary: Uri = format!("ws://{primary_addr}/").parse().unwrap();
+        let secondary: Uri = format!("ws://{secondary_addr}/").parse().unwrap();
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(1),
+            ..Backoff::default()
+        };
+        let failback = Failback {
+            after: Duration::from_millis(50),
+            probe_interval: Duration::from_millis(10),
+            probe_timeout: Duration::from_secs(1),
+        };
+        let mut client = ReconnectingClient::with_endpoints(
+            vec![primary.clone(), secondary.clone()],
+            Config::default(),
+            backoff,
+            (),
+        )
+        .with_failback(failback);
+
+        let start = Instant::now();
+        loop {
+            let frame = client.read_frame().await.unwrap();
+            if frame.data == b"p" {
+                break;
+            }
+            assert_eq!(frame.data, b"s");
+        }
+        assert_eq!(client.endpoint(), &primary);
+        // Probes check the first endpoint's health before switching back.
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(handshakes.get() >= 2);
+    }
+
+    #[compio::test]
+    async fn test_probe_does_not_stall_reads() {
+        let primary_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_addr = primary_listener.local_addr().unwrap();
+        compio::runtime::spawn(async move {
+            // The first handshake fails so the client fails over, probes are
+            // never answered.
+            drop(primary_listener.accept().await.unwrap());
+            let mut streams = Vec::new();
+            loop {
+                streams.push(primary_listener.accept().await.unwrap());
+            }
+        })
+        .detach();
+
+        let secondary_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let secondary_addr = secondary_listener.local_addr().unwrap();
+        compio::runtime::spawn(async move {
+            let (mut stream, _) = secondary_listener.accept().await.unwrap();
+            accept_handshake(&mut stream).await;
+            loop {
+                let BufResult(result, _) = stream.write_all(b"\x82\x01s".to_vec()).await;
+                if result.is_err() {
+                    break;
+                }
+                time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .detach();
+
+        let primary: Uri = format!("ws://{primary_addr}/").parse().unwrap();
+        let secondary: Uri = format!("ws://{secondary_addr}/").parse().unwrap();
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(1),
+            ..Backoff::default()
+        };
+        let failback = Failback {
+            after: Duration::from_secs(60),
+            probe_interval: Duration::from_millis(10),
+            probe_timeout: Duration::from_secs(5),
+        };
+        let mut client = ReconnectingClient::with_endpoints(
+            vec![primary, secondary.clone()],
+            Config::default(),
+            backoff,
+            (),
+        )
+        .with_failback(failback);
+
+        client.read_frame().await.unwrap();
+        let start = Instant::now();
+        for _ in 0..20 {
+            assert_eq!(client.read_frame().await.unwrap().data, b"s");
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(client.endpoint(), &secondary);
+    }
+}
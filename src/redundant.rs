@@ -0,0 +1,287 @@
+use std::{collections::BTreeSet, io};
+
+use compio::io::{AsyncRead, AsyncWrite, util::Splittable};
+use futures_util::{StreamExt, future::LocalBoxFuture, stream::FuturesUnordered};
+
+use crate::{
+    Client, Error, Frame, Opcode, ReconnectHandler, ReconnectingClient, Result,
+    reconnect::parse_close,
+};
+
+/// A connection [`RedundantClient`] reads frames from.
+pub trait FrameReader {
+    fn read_frame(&mut self) -> impl Future<Output = Result<Frame<'_>>>;
+
+    fn send_pong(&mut self, data: &[u8]) -> impl Future<Output = Result<()>>;
+}
+
+impl<S> FrameReader for Client<S>
+where
+    S: Splittable,
+    S::ReadHalf: AsyncRead,
+    S::WriteHalf: AsyncWrite,
+{
+    async fn read_frame(&mut self) -> Result<Frame<'_>> {
+        Client::read_frame(self).await
+    }
+
+    async fn send_pong(&mut self, data: &[u8]) -> Result<()> {
+        Client::send_pong(self, data).await
+    }
+}
+
+impl<H> FrameReader for ReconnectingClient<H>
+where
+    H: ReconnectHandler,
+{
+    async fn read_frame(&mut self) -> Result<Frame<'_>> {
+        ReconnectingClient::read_frame(self).await
+    }
+
+    async fn send_pong(&mut self, data: &[u8]) -> Result<()> {
+        match self.client() {
+            Some(client) => client.send_pong(data).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// A data frame read by [`RedundantClient`].
+#[derive(Clone, Debug)]
+pub struct Message {
+    /// Index of the connection the frame was read from first.
+    pub leg: usize,
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub data: Vec<u8>,
+}
+
+type LegRead<L> = LocalBoxFuture<'static, (usize, L, Result<Message>)>;
+
+/// Reads the same stream from several redundant connections at once and
+/// returns every message once, whichever connection delivers it first.
+///
+/// Duplicates are recognized by the key `key` extracts from a frame, such as
+/// a sequence number. Frames without a key are always returned. The keys of
+/// the most recent [`RedundantClient::with_dedup_window`] frames are
+/// remembered, and frames with a key below all remembered keys are dropped as
+/// well. Frames are deduplicated individually, so the stream should not
+/// fragment its messages.
+///
+/// Keys therefore have to increase over the stream. If they start over, e.g.
+/// because the server reset its sequence numbers, call
+/// [`RedundantClient::reset_dedup`], otherwise every frame is dropped until
+/// the keys catch up with the remembered ones.
+///
+/// Pings are answered and close frames end the connection they arrive on.
+/// When a connection fails, reading continues from the others, so use
+/// [`ReconnectingClient`]s to keep every connection alive.
+pub struct RedundantClient<L, K, F> {
+    reads: FuturesUnordered<LegRead<L>>,
+    key: F,
+    seen: BTreeSet<K>,
+    dedup_window: usize,
+    last_error: Option<Error>,
+}
+
+impl<L, K, F> RedundantClient<L, K, F>
+where
+    L: FrameReader + 'static,
+    K: Ord,
+    F: FnMut(&[u8]) -> Option<K>,
+{
+    pub fn new(legs: impl IntoIterator<Item = L>, key: F) -> Self {
+        Self {
+            reads: legs
+                .into_iter()
+                .enumerate()
+                .map(|(index, leg)| read_message(index, leg))
+                .collect(),
+            key,
+            seen: BTreeSet::new(),
+            dedup_window: 4096,
+            last_error: None,
+        }
+    }
+
+    /// Sets how many keys are remembered to recognize duplicates, 4096 by
+    /// default. It has to cover how far the connections may lag behind each
+    /// other.
+    #[must_use]
+    pub fn with_dedup_window(mut self, dedup_window: usize) -> Self {
+        self.dedup_window = dedup_window.max(1);
+        self
+    }
+
+    /// Forgets all remembered keys, so that the next frame with any key is
+    /// returned. Call it when the keys start over.
+    pub fn reset_dedup(&mut self) {
+        self.seen.clear();
+    }
+
+    /// Returns the number of connections still being read from.
+    #[must_use]
+    pub fn legs(&self) -> usize {
+        self.reads.len()
+    }
+
+    /// Reads the next message that has not been read from another connection
+    /// yet. Fails with the error of the last connection once all connections
+    /// failed.
+    pub async fn read_message(&mut self) -> Result<Message> {
+        while let Some((index, leg, res)) = self.reads.next().await {
+            match res {
+                Ok(message) => {
+                    self.reads.push(read_message(index, leg));
+                    if !self.is_duplicate(&message.data) {
+                        return Ok(message);
+                    }
+                }
+                // The connection is dropped along with `leg`.
+                Err(err) => self.last_error = Some(err),
+            }
+        }
+        Err(self
+            .last_error
+            .take()
+            .unwrap_or_else(|| io::Error::from(io::ErrorKind::NotConnected).into()))
+    }
+
+    fn is_duplicate(&mut self, data: &[u8]) -> bool {
+        let Some(key) = (self.key)(data) else {
+            return false;
+        };
+        let below_window = self.seen.len() >= self.dedup_window
+            && self.seen.first().is_some_and(|first| key < *first);
+        if below_window || self.seen.contains(&key) {
+            return true;
+        }
+        self.seen.insert(key);
+        if self.seen.len() > self.dedup_window {
+            self.seen.pop_first();
+        }
+        false
+    }
+}
+
+/// Reads the next data frame from `leg`, answering pings on the way. Takes and
+/// returns `leg` so the read can be polled alongside the other connections.
+fn read_message<L>(index: usize, mut leg: L) -> LegRead<L>
+where
+    L: FrameReader + 'static,
+{
+    Box::pin(async move {
+        let res = loop {
+            let frame = match leg.read_frame().await {
+                Ok(frame) => frame,
+                Err(err) => break Err(err),
+            };
+            match frame.opcode {
+                Opcode::Text | Opcode::Binary | Opcode::Continuation => {
+                    break Ok(Message {
+                        leg: index,
+                        fin: frame.fin,
+                        opcode: frame.opcode,
+                        data: frame.data.to_vec(),
+                    });
+                }
+                Opcode::Ping => {
+                    let data = frame.data.to_vec();
+                    if let Err(err) = leg.send_pong(&data).await {
+                        break Err(err);
+                    }
+                }
+                Opcode::Close => {
+                    let (code, reason) = parse_close(frame.data);
+                    break Err(Error::Closed { code, reason });
+                }
+                _ => {}
+            }
+        };
+        (index, leg, res)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use compio::{
+        BufResult,
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::Config;
+
+    /// Opens a client whose server sends a binary frame for each of `keys` and
+    /// then closes the connection.
+    async fn leg(keys: &'static [u8]) -> Client<TcpStream> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server_stream, _) = listener.accept().await.unwrap();
+        compio::runtime::spawn(async move {
+            let frames = keys.iter().flat_map(|&key| [0x82, 0x01, key]).collect();
+            let BufResult(result, _) = server_stream.write_all::<Vec<u8>>(frames).await;
+            result.unwrap();
+        })
+        .detach();
+        Client::new(stream, &Config::default())
+    }
+
+    #[compio::test]
+    async fn test_deduplication() {
+        let legs = [leg(&[1, 2, 3]).await, leg(&[2, 3, 4]).await];
+        let mut client = RedundantClient::new(legs, |data: &[u8]| data.first().copied());
+
+        let mut keys = Vec::new();
+        for _ in 0..4 {
+            keys.push(client.read_message().await.unwrap().data[0]);
+        }
+        keys.sort_unstable();
+        assert_eq!(keys, [1, 2, 3, 4]);
+
+        // Both connections end after their frames.
+        assert!(client.read_message().await.is_err());
+        assert_eq!(client.legs(), 0);
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let mut client = RedundantClient::<Client<TcpStream>, _, _>::new([], |data: &[u8]| {
+            data.first().copied()
+        })
+        .with_dedup_window(2);
+
+        assert!(!client.is_duplicate(&[1]));
+        assert!(!client.is_duplicate(&[3]));
+        assert!(client.is_duplicate(&[3]));
+        // A gap is filled in by another connection.
+        assert!(!client.is_duplicate(&[2]));
+        // 1 fell out of the window but is older than anything remembered.
+        assert!(client.is_duplicate(&[1]));
+        assert!(!client.is_duplicate(&[4]));
+        assert!(client.is_duplicate(&[2]));
+        // Frames without a key are never duplicates.
+        assert!(!client.is_duplicate(&[]));
+        assert!(!client.is_duplicate(&[]));
+    }
+
+    #[test]
+    fn test_reset_dedup() {
+        let mut client = RedundantClient::<Client<TcpStream>, _, _>::new([], |data: &[u8]| {
+            data.first().copied()
+        })
+        .with_dedup_window(2);
+
+        assert!(!client.is_duplicate(&[7]));
+        assert!(!client.is_duplicate(&[8]));
+        // The sequence starts over.
+        assert!(client.is_duplicate(&[1]));
+        client.reset_dedup();
+        assert!(!client.is_duplicate(&[1]));
+        assert!(client.is_duplicate(&[1]));
+        assert!(!client.is_duplicate(&[2]));
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use compio::net::ToSocketAddrsAsync;
+use futures_util::future::LocalBoxFuture;
+
+/// Resolves host names to the addresses to connect to, see
+/// [`Config::resolver`](crate::Config::resolver).
+///
+/// The resolver is not consulted for hosts that are IP literals.
+pub trait Resolver: Send + Sync {
+    /// Returns the addresses of `host`, all with the given `port`, in order of
+    /// preference.
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> LocalBoxFuture<'a, io::Result<Vec<SocketAddr>>>;
+}
+
+/// Resolves host names with the resolver of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> LocalBoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move { Ok((host, port).to_socket_addrs_async().await?.collect()) })
+    }
+}
+
+/// Resolves host names from a fixed map, e.g. to pin the addresses of
+/// colocated endpoints or to point names at a local stand-in in tests. Hosts
+/// missing from the map are resolved with [`SystemResolver`].
+#[derive(Clone, Debug, Default)]
+pub struct StaticResolver {
+    pub hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl StaticResolver {
+    #[must_use]
+    pub fn new(hosts: HashMap<String, Vec<IpAddr>>) -> Self {
+        Self { hosts }
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> LocalBoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        match self.hosts.get(host) {
+            Some(ips) => {
+                let addrs = ips.iter().map(|&ip| SocketAddr::new(ip, port)).collect();
+                Box::pin(async move { Ok(addrs) })
+            }
+            None => SystemResolver.resolve(host, port),
+        }
+    }
+}
+
+/// Resolves `host` with `resolver`, or [`SystemResolver`] if none is set.
+pub(crate) async fn resolve(
+    resolver: Option<&dyn Resolver>,
+    host: &str,
+    port: u16,
+) -> io::Result<Vec<SocketAddr>> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    resolver
+        .unwrap_or(&SystemResolver)
+        .resolve(host, port)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[compio::test]
+    async fn test_static_resolver() {
+        let resolver = StaticResolver::new(HashMap::from([(
+            "venue.example.com".to_string(),
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+        )]));
+        let addrs = resolve(Some(&resolver), "venue.example.com", 443)
+            .await
+            .unwrap();
+        assert_eq!(addrs, ["127.0.0.1:443".parse::<SocketAddr>().unwrap()]);
+    }
+
+    #[compio::test]
+    async fn test_ip_literal_skips_resolver() {
+        struct FailingResolver;
+
+        impl Resolver for FailingResolver {
+            fn resolve<'a>(
+                &'a self,
+                _host: &'a str,
+                _port: u16,
+            ) -> LocalBoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+                Box::pin(async { Err(io::Error::other("resolver consulted")) })
+            }
+        }
+
+        let addrs = resolve(Some(&FailingResolver), "::1", 9001).await.unwrap();
+        assert_eq!(addrs, ["[::1]:9001".parse::<SocketAddr>().unwrap()]);
+    }
+}
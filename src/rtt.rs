@@ -0,0 +1,157 @@
+use std::time::{Duration, Instant};
+
+/// Length of the payload of pings sent by
+/// [`Client::send_rtt_ping`](crate::Client::send_rtt_ping): a sequence number
+/// followed by the nanoseconds since the connection was opened.
+pub(crate) const PING_PAYLOAD_LEN: usize = 16;
+
+/// Number of buckets in [`RttStats::histogram`].
+pub const RTT_HISTOGRAM_BUCKETS: usize = 32;
+
+/// Round-trip times measured with pings sent by
+/// [`Client::send_rtt_ping`](crate::Client::send_rtt_ping) and keepalive pings.
+#[derive(Clone, Debug, Default)]
+pub struct RttStats {
+    last: Option<Duration>,
+    min: Option<Duration>,
+    ewma: Option<Duration>,
+    count: u64,
+    histogram: [u64; RTT_HISTOGRAM_BUCKETS],
+}
+
+impl RttStats {
+    /// Round-trip time of the most recently answered ping.
+    #[must_use]
+    pub fn last(&self) -> Option<Duration> {
+        self.last
+    }
+
+    /// Lowest round-trip time measured.
+    #[must_use]
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    /// Exponentially weighted moving average of the round-trip times, weighing
+    /// each new sample by 1/8 like TCP's smoothed RTT.
+    #[must_use]
+    pub fn ewma(&self) -> Option<Duration> {
+        self.ewma
+    }
+
+    /// Number of round-trip times measured.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Number of round-trip times per power of two microseconds: bucket `i`
+    /// counts round-trip times of `2^i` up to `2^(i + 1)` microseconds, the
+    /// first bucket also counts anything below a microsecond and the last one
+    /// anything above.
+    #[must_use]
+    pub fn histogram(&self) -> &[u64; RTT_HISTOGRAM_BUCKETS] {
+        &self.histogram
+    }
+
+    pub(crate) fn record(&mut self, rtt: Duration) {
+        self.last = Some(rtt);
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.ewma = Some(match self.ewma {
+            Some(ewma) => (ewma * 7 + rtt) / 8,
+            None => rtt,
+        });
+        self.count += 1;
+
+        let micros = u64::try_from(rtt.as_micros()).unwrap_or(u64::MAX);
+        let bucket = micros.max(1).ilog2() as usize;
+        self.histogram[bucket.min(RTT_HISTOGRAM_BUCKETS - 1)] += 1;
+    }
+}
+
+/// Encodes ping payloads and matches pongs against them.
+pub(crate) struct PingClock {
+    epoch: Instant,
+    next_seq: u64,
+    next_unanswered: u64,
+}
+
+impl PingClock {
+    pub(crate) fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            next_seq: 0,
+            next_unanswered: 0,
+        }
+    }
+
+    pub(crate) fn ping_payload(&mut self, now: Instant) -> [u8; PING_PAYLOAD_LEN] {
+        let nanos = u64::try_from((now - self.epoch).as_nanos()).unwrap_or(u64::MAX);
+        let mut payload = [0; PING_PAYLOAD_LEN];
+        payload[..8].copy_from_slice(&self.next_seq.to_be_bytes());
+        payload[8..].copy_from_slice(&nanos.to_be_bytes());
+        self.next_seq += 1;
+        payload
+    }
+
+    /// Returns the round-trip time of the ping answered by a pong with
+    /// `payload`, or `None` if the pong does not answer an outstanding ping
+    /// of ours.
+    pub(crate) fn rtt(&mut self, payload: &[u8], now: Instant) -> Option<Duration> {
+        let payload: &[u8; PING_PAYLOAD_LEN] = payload.try_into().ok()?;
+        let seq = u64::from_be_bytes(payload[..8].try_into().unwrap());
+        let nanos = u64::from_be_bytes(payload[8..].try_into().unwrap());
+        if !(self.next_unanswered..self.next_seq).contains(&seq) {
+            return None;
+        }
+        self.next_unanswered = seq + 1;
+        let sent = self.epoch + Duration::from_nanos(nanos);
+        Some(now.saturating_duration_since(sent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test]
+    fn test_ping_clock() {
+        let mut clock = PingClock::new();
+        let sent = Instant::now();
+        let first = clock.ping_payload(sent);
+        let second = clock.ping_payload(sent + Duration::from_millis(1));
+
+        let received = sent + Duration::from_millis(3);
+        assert_eq!(clock.rtt(&second, received), Some(Duration::from_millis(2)));
+        // Pongs for pings answered out of order or more than once are ignored.
+        assert_eq!(clock.rtt(&first, received), None);
+        assert_eq!(clock.rtt(&second, received), None);
+    }
+
+    #[test_case(&[]; "empty")]
+    #[test_case(b"application ping"; "foreign payload")]
+    #[test_case(&[0; PING_PAYLOAD_LEN]; "unsent sequence number")]
+    fn test_ping_clock_ignores_foreign_pongs(payload: &[u8]) {
+        let mut clock = PingClock::new();
+        assert_eq!(clock.rtt(payload, Instant::now()), None);
+    }
+
+    #[test]
+    fn test_rtt_stats() {
+        let mut stats = RttStats::default();
+        stats.record(Duration::from_micros(800));
+        stats.record(Duration::from_micros(400));
+        stats.record(Duration::from_nanos(10));
+
+        assert_eq!(stats.last(), Some(Duration::from_nanos(10)));
+        assert_eq!(stats.min(), Some(Duration::from_nanos(10)));
+        assert_eq!(stats.count(), 3);
+        assert_eq!(stats.histogram()[0], 1);
+        assert_eq!(stats.histogram()[8], 1);
+        assert_eq!(stats.histogram()[9], 1);
+        // (800 * 7 + 400) / 8 = 750, then (750 * 7 + 0.01) / 8.
+        assert_eq!(stats.ewma(), Some(Duration::from_nanos(656_251)));
+    }
+}
@@ -0,0 +1,289 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use compio::{net::TcpStream, runtime::fd::PollFd};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+
+/// Options applied to the TCP socket of a connection before the WebSocket
+/// handshake, see [`Config::socket`](crate::Config::socket).
+///
+/// Options marked as Linux only are ignored on other platforms.
+#[derive(Clone, Debug)]
+pub struct SocketConfig {
+    /// Disables Nagle's algorithm with `TCP_NODELAY`. Enabled by default.
+    pub nodelay: bool,
+    /// Size of the kernel receive buffer (`SO_RCVBUF`).
+    pub recv_buffer_size: Option<usize>,
+    /// Size of the kernel send buffer (`SO_SNDBUF`).
+    pub send_buffer_size: Option<usize>,
+    /// Sends ACKs immediately instead of delaying them (`TCP_QUICKACK`). The
+    /// kernel may fall back to delayed ACKs later on. Linux only.
+    pub quickack: bool,
+    /// Microseconds to busy poll the device queue for incoming packets on
+    /// blocking reads (`SO_BUSY_POLL`). Linux only.
+    pub busy_poll: Option<u32>,
+    /// Type of service, e.g. a DSCP value shifted left by two bits (`IP_TOS`
+    /// or `IPV6_TCLASS`). Linux only.
+    pub tos: Option<u32>,
+    /// Priority of the packets sent on the socket for queueing disciplines
+    /// (`SO_PRIORITY`). Linux only.
+    pub priority: Option<u32>,
+    /// Idle time before TCP keepalive probes are sent. Keepalive probes are
+    /// not sent if not set.
+    pub keepalive_time: Option<Duration>,
+    /// Time between TCP keepalive probes. Linux only.
+    pub keepalive_interval: Option<Duration>,
+    /// Number of unanswered TCP keepalive probes before the connection is
+    /// dropped. Linux only.
+    pub keepalive_retries: Option<u32>,
+    /// Time sent data may remain unacknowledged before the connection is
+    /// dropped (`TCP_USER_TIMEOUT`). Linux only.
+    pub user_timeout: Option<Duration>,
+    /// Local address to bind the socket to before connecting, e.g. the address
+    /// of a specific NIC on a multi-homed host. Use port 0 to let the kernel
+    /// pick the port, a fixed port can only be used by one connection attempt
+    /// at a time.
+    pub bind_addr: Option<SocketAddr>,
+    /// Network interface to bind the socket to before connecting
+    /// (`SO_BINDTODEVICE`), e.g. `eth1`. Linux only.
+    pub bind_device: Option<String>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            quickack: false,
+            busy_poll: None,
+            tos: None,
+            priority: None,
+            keepalive_time: None,
+            keepalive_interval: None,
+            keepalive_retries: None,
+            user_timeout: None,
+            bind_addr: None,
+            bind_device: None,
+        }
+    }
+}
+
+impl SocketConfig {
+    /// Connects to `addr` with the options applied, binding the socket first
+    /// if [`Self::bind_addr`] or [`Self::bind_device`] is set.
+    ///
+    /// Options that affect the TCP handshake, such as the buffer sizes the
+    /// window scale is derived from, are set before connecting. The socket is
+    /// connected without blocking and the runtime is polled for the connection
+    /// to complete, so dropping the future aborts the attempt.
+    pub(crate) async fn connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        self.apply_before_connect(&SockRef::from(&socket), addr.is_ipv6())?;
+        #[cfg(target_os = "linux")]
+        if let Some(device) = &self.bind_device {
+            socket.bind_device(Some(device.as_bytes()))?;
+        }
+        if let Some(bind_addr) = self.bind_addr {
+            socket.bind(&bind_addr.into())?;
+        }
+
+        socket.set_nonblocking(true)?;
+        match socket.connect(&addr.into()) {
+            Ok(()) => {}
+            Err(err) if is_in_progress(&err) => {
+                // Polls a duplicate of the socket so it can be handed over to
+                // the stream afterwards.
+                PollFd::new(socket.try_clone()?)?.connect_ready().await?;
+                if let Some(err) = socket.take_error()? {
+                    return Err(err);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+        socket.set_nonblocking(false)?;
+        let stream = TcpStream::from_std(socket.into())?;
+        self.apply_after_connect(&SockRef::from(&stream))?;
+        Ok(stream)
+    }
+
+    /// Applies the options to an accepted connection to tune it the same way
+    /// as the connections the client opens. The buffer sizes are better set on
+    /// the listening socket, as the window scale is fixed during the TCP
+    /// handshake.
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        let socket = SockRef::from(stream);
+        self.apply_before_connect(&socket, stream.peer_addr()?.is_ipv6())?;
+        self.apply_after_connect(&socket)
+    }
+
+    /// Applies the options that have to be set before the SYN is sent to take
+    /// full effect.
+    #[cfg_attr(not(target_os = "linux"), expect(unused_variables))]
+    fn apply_before_connect(&self, socket: &SockRef<'_>, ipv6: bool) -> io::Result<()> {
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(tos) = self.tos {
+                if ipv6 {
+                    socket.set_tclass_v6(tos)?;
+                } else {
+                    socket.set_tos(tos)?;
+                }
+            }
+            if let Some(priority) = self.priority {
+                set_int_option(socket, libc::SO_PRIORITY, priority)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_after_connect(&self, socket: &SockRef<'_>) -> io::Result<()> {
+        socket.set_nodelay(self.nodelay)?;
+        if let Some(time) = self.keepalive_time {
+            let keepalive = TcpKeepalive::new().with_time(time);
+            #[cfg(target_os = "linux")]
+            let keepalive = match self.keepalive_interval {
+                Some(interval) => keepalive.with_interval(interval),
+                None => keepalive,
+            };
+            #[cfg(target_os = "linux")]
+            let keepalive = match self.keepalive_retries {
+                Some(retries) => keepalive.with_retries(retries),
+                None => keepalive,
+            };
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            if self.quickack {
+                socket.set_quickack(true)?;
+            }
+            if let Some(busy_poll) = self.busy_poll {
+                set_int_option(socket, libc::SO_BUSY_POLL, busy_poll)?;
+            }
+            if let Some(timeout) = self.user_timeout {
+                socket.set_tcp_user_timeout(Some(timeout))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a non-blocking connect failed only because it is still in progress.
+fn is_in_progress(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    if err.raw_os_error() == Some(libc::EINPROGRESS) {
+        return true;
+    }
+    err.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Sets a `SOL_SOCKET` option that socket2 has no setter for.
+#[cfg(target_os = "linux")]
+fn set_int_option(socket: &SockRef<'_>, name: libc::c_int, value: u32) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let value = libc::c_int::try_from(value).map_err(io::Error::other)?;
+    // SAFETY: `value` outlives the call and its size is passed along.
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            name,
+            (&raw const value).cast(),
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use compio::net::TcpListener;
+
+    use super::*;
+
+    #[compio::test]
+    async fn test_connect_bound() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = SocketConfig {
+            bind_addr: Some("127.0.0.1:0".parse().unwrap()),
+            ..SocketConfig::default()
+        };
+        let stream = config
+            .connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let local_addr = stream.local_addr().unwrap();
+        assert!(local_addr.ip().is_loopback());
+        assert_ne!(local_addr.port(), 0);
+    }
+
+    #[compio::test]
+    async fn test_connect_applies_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = SocketConfig {
+            recv_buffer_size: Some(64 * 1024),
+            keepalive_time: Some(Duration::from_secs(30)),
+            ..SocketConfig::default()
+        };
+        let stream = config
+            .connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+    }
+
+    #[compio::test]
+    async fn test_connect_bound_refused() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = SocketConfig {
+            bind_addr: Some("127.0.0.1:0".parse().unwrap()),
+            ..SocketConfig::default()
+        };
+        let err = config.connect(addr).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[compio::test]
+    async fn test_apply() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let config = SocketConfig {
+            recv_buffer_size: Some(64 * 1024),
+            keepalive_time: Some(Duration::from_secs(30)),
+            keepalive_interval: Some(Duration::from_secs(5)),
+            keepalive_retries: Some(3),
+            ..SocketConfig::default()
+        };
+        config.apply(&stream).unwrap();
+
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+    }
+}
@@ -0,0 +1,162 @@
+use std::io;
+
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+use compio::tls::TlsStream;
+use compio::{
+    BufResult,
+    buf::{IoBuf, IoBufMut, IoVectoredBuf, IoVectoredBufMut},
+    io::{AsyncRead, AsyncWrite, util::Splittable},
+    net::TcpStream,
+};
+
+/// A TCP stream that may or may not be wrapped in TLS, depending on the scheme
+/// of the URI it was connected to. Without a TLS backend enabled, only plain
+/// streams exist.
+pub enum MaybeTlsStream {
+    Plain(TcpStream),
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    Tls(TlsStream<TcpStream>),
+}
+
+impl AsyncRead for MaybeTlsStream {
+    async fn read<B: IoBufMut>(&mut self, buf: B) -> BufResult<usize, B> {
+        match self {
+            Self::Plain(stream) => stream.read(buf).await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(stream) => stream.read(buf).await,
+        }
+    }
+
+    async fn read_vectored<V: IoVectoredBufMut>(&mut self, buf: V) -> BufResult<usize, V> {
+        match self {
+            Self::Plain(stream) => stream.read_vectored(buf).await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(stream) => stream.read_vectored(buf).await,
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        match self {
+            Self::Plain(stream) => stream.write(buf).await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(stream) => stream.write(buf).await,
+        }
+    }
+
+    async fn write_vectored<T: IoVectoredBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        match self {
+            Self::Plain(stream) => stream.write_vectored(buf).await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(stream) => stream.write_vectored(buf).await,
+        }
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush().await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(stream) => stream.flush().await,
+        }
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.shutdown().await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(stream) => stream.shutdown().await,
+        }
+    }
+}
+
+impl Splittable for MaybeTlsStream {
+    type ReadHalf = MaybeTlsReadHalf;
+    type WriteHalf = MaybeTlsWriteHalf;
+
+    fn split(self) -> (Self::ReadHalf, Self::WriteHalf) {
+        match self {
+            Self::Plain(stream) => {
+                let (read_half, write_half) = Splittable::split(stream);
+                (
+                    MaybeTlsReadHalf::Plain(read_half),
+                    MaybeTlsWriteHalf::Plain(write_half),
+                )
+            }
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(stream) => {
+                let (read_half, write_half) = Splittable::split(stream);
+                (
+                    MaybeTlsReadHalf::Tls(read_half),
+                    MaybeTlsWriteHalf::Tls(write_half),
+                )
+            }
+        }
+    }
+}
+
+/// The read half of a [`MaybeTlsStream`].
+pub enum MaybeTlsReadHalf {
+    Plain(<TcpStream as Splittable>::ReadHalf),
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    Tls(<TlsStream<TcpStream> as Splittable>::ReadHalf),
+}
+
+impl AsyncRead for MaybeTlsReadHalf {
+    async fn read<B: IoBufMut>(&mut self, buf: B) -> BufResult<usize, B> {
+        match self {
+            Self::Plain(half) => half.read(buf).await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(half) => half.read(buf).await,
+        }
+    }
+
+    async fn read_vectored<V: IoVectoredBufMut>(&mut self, buf: V) -> BufResult<usize, V> {
+        match self {
+            Self::Plain(half) => half.read_vectored(buf).await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(half) => half.read_vectored(buf).await,
+        }
+    }
+}
+
+/// The write half of a [`MaybeTlsStream`].
+pub enum MaybeTlsWriteHalf {
+    Plain(<TcpStream as Splittable>::WriteHalf),
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    Tls(<TlsStream<TcpStream> as Splittable>::WriteHalf),
+}
+
+impl AsyncWrite for MaybeTlsWriteHalf {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        match self {
+            Self::Plain(half) => half.write(buf).await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(half) => half.write(buf).await,
+        }
+    }
+
+    async fn write_vectored<T: IoVectoredBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        match self {
+            Self::Plain(half) => half.write_vectored(buf).await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(half) => half.write_vectored(buf).await,
+        }
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(half) => half.flush().await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(half) => half.flush().await,
+        }
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(half) => half.shutdown().await,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            Self::Tls(half) => half.shutdown().await,
+        }
+    }
+}